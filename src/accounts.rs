@@ -0,0 +1,145 @@
+use crate::snapshot::write_atomically;
+use log::{error, info};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const MAX_USERNAME: usize = 20;
+const MIN_PASSWORD: usize = 6;
+// the password is hashed over and over, so that guessing it from the file takes long
+const ROUNDS: usize = 10_000;
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+    salt: String,
+    hash: String,
+}
+
+pub struct Accounts {
+    // the registered players, saved to a json file whenever somebody registers
+    path: PathBuf,
+    accounts: Mutex<BTreeMap<String, Account>>,
+}
+
+impl Accounts {
+    pub fn load(path: &Path) -> Result<Accounts, String> {
+        // the file is created with the first account
+        let accounts = match fs::read_to_string(path) {
+            Ok(text) => {
+                serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        info!("loaded {} accounts", accounts.len());
+        Ok(Accounts {
+            path: path.to_path_buf(),
+            accounts: Mutex::new(accounts),
+        })
+    }
+
+    pub fn register(&self, username: &str, password: &str) -> Result<(), String> {
+        check_username(username)?;
+        if password.chars().count() < MIN_PASSWORD {
+            return Err(format!(
+                "Passwords must be at least {} characters long",
+                MIN_PASSWORD
+            ));
+        }
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.contains_key(username) {
+            return Err("This username is already taken".to_string());
+        }
+        let salt: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+        let hash = hash(&salt, password);
+        accounts.insert(username.to_string(), Account { salt, hash });
+        if let Err(e) = self.save(&accounts) {
+            error!("could not save the accounts: {}", e);
+            accounts.remove(username);
+            return Err("The account could not be saved".to_string());
+        }
+        info!("{} registered", username);
+        Ok(())
+    }
+
+    pub fn login(&self, username: &str, password: &str) -> Result<(), String> {
+        let accounts = self.accounts.lock().unwrap();
+        match accounts.get(username) {
+            Some(account) if hash(&account.salt, password) == account.hash => {
+                info!("{} logged in", username);
+                Ok(())
+            }
+            _ => Err("Wrong username or password".to_string()),
+        }
+    }
+
+    fn save(&self, accounts: &BTreeMap<String, Account>) -> io::Result<()> {
+        write_atomically(&self.path, &serde_json::to_string_pretty(accounts)?)
+    }
+}
+
+pub fn check_username(username: &str) -> Result<(), String> {
+    if username.is_empty() || username.chars().count() > MAX_USERNAME {
+        Err(format!(
+            "Usernames must be 1 to {} characters long",
+            MAX_USERNAME
+        ))
+    } else if !username
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        Err("Usernames may only contain letters, digits, '-' and '_'".to_string())
+    } else if is_bot_name(username) {
+        Err("Names like bot1 are reserved for bots".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn is_bot_name(username: &str) -> bool {
+    // "bot" followed by a number
+    match username.strip_prefix("bot") {
+        Some(number) => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+fn hash(salt: &str, password: &str) -> String {
+    let mut digest = Sha256::digest(format!("{}{}", salt, password).as_bytes());
+    for _ in 1..ROUNDS {
+        digest = Sha256::digest(digest);
+    }
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_register_login() {
+        let path = env::temp_dir().join(format!("tichu-accounts-{}.json", std::process::id()));
+        let accounts = Accounts::load(&path).unwrap();
+        assert!(accounts.register("alice", "secret").is_ok());
+        assert!(accounts.register("alice", "another").is_err());
+        assert!(accounts.register("bob", "short").is_err());
+        assert!(accounts.register("bob,carol", "secret").is_err());
+        assert!(accounts.register("bot2", "secret").is_err());
+        assert!(accounts.register("bottle", "secret").is_ok());
+        assert!(accounts.login("alice", "secret").is_ok());
+        assert!(accounts.login("alice", "Secret").is_err());
+        assert!(accounts.login("bob", "secret").is_err());
+        // the accounts are still there after a restart
+        let loaded = Accounts::load(&path).unwrap();
+        assert!(loaded.login("alice", "secret").is_ok());
+        assert!(!fs::read_to_string(&path).unwrap().contains("secret"));
+        fs::remove_file(&path).unwrap();
+    }
+}
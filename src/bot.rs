@@ -0,0 +1,330 @@
+use crate::combinations::Trick;
+use crate::deck::{Card, Color, Kind, RegularKind, SpecialKind};
+use crate::player::Player;
+use crate::protocol::Message;
+use crate::tichuserver::TichuConnection;
+use itertools::Itertools;
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+
+pub fn spawn(game: Arc<TichuConnection>, seat: usize, inbox: Receiver<Message>) {
+    // the bot gets the same messages as a client would and answers in its own thread
+    thread::spawn(move || {
+        let mut bot = Bot {
+            game,
+            seat,
+            pending: VecDeque::new(),
+            gave_in: false,
+        };
+        bot.run(inbox);
+    });
+}
+
+struct Bot {
+    game: Arc<TichuConnection>,
+    seat: usize,
+    pending: VecDeque<String>, // commands that have not been answered yet
+    gave_in: bool,             // whether the bot fell back to the lowest move this turn
+}
+
+impl Bot {
+    fn run(&mut self, inbox: Receiver<Message>) {
+        for message in inbox.iter() {
+            match message {
+                Message::Push(topic, _) => match topic {
+                    "start" | "newgame" => self.take_cards(),
+                    // after a round, the cards are dealt again right away
+                    "clearcards" if !self.game.inspect(self.seat, |_, game| game.is_over()) => {
+                        self.take_cards()
+                    }
+                    "yourturn" => {
+                        self.gave_in = false;
+                        self.play()
+                    }
+                    "givedragon" => self.command(&format!("givedragon {}", (self.seat + 1) % 4)),
+                    "gameover" => self.command("rematch yes"),
+                    "shutdown" => break,
+                    _ => {}
+                },
+                Message::Ok(_) => {
+                    self.pending.pop_front();
+                }
+                Message::Err(e) => {
+                    let command = self.pending.pop_front().unwrap_or_default();
+                    warn!("bot {} got an error for '{}': {}", self.seat, command, e);
+                    // if the server doesn't like the move, don't hold up the game. if passing
+                    // isn't allowed either (e.g. when leading), play the lowest legal move
+                    if command.starts_with("play") {
+                        self.command("pass");
+                    } else if command == "pass" && !self.gave_in {
+                        self.gave_in = true;
+                        self.give_in();
+                    }
+                }
+            }
+        }
+        debug!("bot {} leaves the table", self.seat);
+    }
+
+    fn command(&mut self, msg: &str) {
+        // the answer is in the inbox as soon as handle_message returns
+        self.pending.push_back(msg.to_string());
+        self.game.handle_message(self.seat, msg);
+    }
+
+    fn take_cards(&mut self) {
+        self.command("takecards");
+        self.command("takecards");
+        let exchange = self.game.inspect(self.seat, |player, game| {
+            if game.rules().exchange {
+                Some(choose_exchange(&player.get_hand()))
+            } else {
+                None
+            }
+        });
+        if let Some(exchange) = exchange {
+            self.command(&format!("exchange {}", join_ids(&exchange)));
+        }
+    }
+
+    fn play(&mut self) {
+        let seat = self.seat;
+        let cards = self.game.inspect(seat, |player, game| {
+            let partner_wins = game.get_trick_owner() == Some((seat + 2) % 4);
+            choose_play(
+                player,
+                game.get_current_trick(),
+                game.get_wish(),
+                partner_wins,
+            )
+        });
+        match cards {
+            Some(cards) => self.command(&format!("play {}", join_ids(&cards))),
+            None => self.command("pass"),
+        }
+    }
+
+    fn give_in(&mut self) {
+        let cards = self.game.inspect(self.seat, |player, game| {
+            lowest_move(player, game.get_current_trick(), game.get_wish())
+        });
+        match cards {
+            Some(cards) => self.command(&format!("play {}", join_ids(&cards))),
+            None => warn!("bot {} has no move left", self.seat),
+        }
+    }
+}
+
+fn join_ids(ids: &[usize]) -> String {
+    ids.iter().map(|i| i.to_string()).join(" ")
+}
+
+fn choose_exchange(hand: &[(usize, &Card)]) -> Vec<usize> {
+    // the two lowest cards go to the opponents, the highest one to the partner.
+    // cards of bombs, the dragon and the phoenix are kept
+    let bombs = bomb_cards(hand);
+    let mut cards: Vec<&(usize, &Card)> = hand
+        .iter()
+        .filter(|(i, c)| !bombs.contains(i) && !is_high_special(c))
+        .collect();
+    if cards.len() < 3 {
+        cards = hand.iter().collect();
+    }
+    cards.sort_by_key(|(_, c)| c.rank);
+    vec![cards[0].0, cards[cards.len() - 1].0, cards[1].0]
+}
+
+fn choose_play(
+    player: &Player,
+    trick_to_top: Option<&Trick>,
+    wish: Option<RegularKind>,
+    partner_wins: bool,
+) -> Option<Vec<usize>> {
+    // returns the identifyers of the cards to play, None to pass
+    let bombs = bomb_cards(&player.get_hand());
+    // if the wish can be fulfilled, passing is not an option
+    let forced = wish.is_some_and(|kind| player.can_fulfil_wish(trick_to_top, kind));
+    let mut moves = player.legal_moves(trick_to_top, wish);
+    if !forced {
+        match trick_to_top {
+            // lead low singlets
+            None => moves.retain(|(_, trick)| trick.cards.len() == 1),
+            // let the partner win the trick
+            Some(_) if partner_wins => return None,
+            // hold bombs
+            Some(_) => moves.retain(|(cards, _)| !cards.iter().any(|i| bombs.contains(i))),
+        }
+    }
+    // the lowest move, preferably without breaking up a bomb or using the dragon or phoenix
+    moves
+        .into_iter()
+        .min_by_key(|(cards, trick)| {
+            (
+                cards.iter().any(|i| bombs.contains(i)),
+                trick.cards.iter().any(is_high_special),
+                trick.rank(),
+                cards.len(),
+            )
+        })
+        .map(|(cards, _)| cards)
+}
+
+pub fn lowest_move(
+    player: &Player,
+    trick_to_top: Option<&Trick>,
+    wish: Option<RegularKind>,
+) -> Option<Vec<usize>> {
+    // a move that is always legal: the lowest single card (or the smallest trick) that
+    // tops trick_to_top and fulfils the wish, if possible without the dragon or phoenix
+    player
+        .legal_moves(trick_to_top, wish)
+        .into_iter()
+        .min_by_key(|(cards, trick)| {
+            (
+                trick.cards.iter().any(is_high_special),
+                cards.len(),
+                trick.rank(),
+            )
+        })
+        .map(|(cards, _)| cards)
+}
+
+pub fn is_high_special(card: &Card) -> bool {
+    card.kind == Kind::Special(SpecialKind::Dragon)
+        || card.kind == Kind::Special(SpecialKind::Phoenix)
+}
+
+fn bomb_cards(hand: &[(usize, &Card)]) -> Vec<usize> {
+    // identifyers of all cards that are part of a four of a kind or a straight flush
+    let mut ids = Vec::new();
+    let regular: Vec<&(usize, &Card)> = hand
+        .iter()
+        .filter(|(_, c)| matches!(c.kind, Kind::Regular(_)))
+        .sorted_by_key(|(_, c)| c.rank)
+        .collect();
+    for (_, group) in &regular.iter().group_by(|(_, c)| c.rank) {
+        let group: Vec<_> = group.collect();
+        if group.len() == 4 {
+            ids.extend(group.iter().map(|(i, _)| *i));
+        }
+    }
+    for color in &[Color::Black, Color::Blue, Color::Green, Color::Red] {
+        // runs of at least five consecutive ranks in the same color
+        let cards: Vec<&&(usize, &Card)> = regular
+            .iter()
+            .filter(|(_, c)| c.color == Some(*color))
+            .collect();
+        let mut run: Vec<usize> = Vec::new();
+        for (n, (i, card)) in cards.iter().enumerate() {
+            if n > 0 && cards[n - 1].1.rank + 1 != card.rank {
+                if run.len() >= 5 {
+                    ids.extend(&run);
+                }
+                run.clear();
+            }
+            run.push(*i);
+        }
+        if run.len() >= 5 {
+            ids.extend(&run);
+        }
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_with(cards: &[Card]) -> Player {
+        // the cards get the identifyers 0, 1, 2, ...
+        let mut player = Player::new("bot".to_string());
+        player.take_new_hand(cards.to_vec());
+        player
+    }
+
+    #[test]
+    fn test_lead_low_singlet() {
+        let player = player_with(&[
+            Card::regular(RegularKind::Nine, Color::Red),
+            Card::regular(RegularKind::Three, Color::Red),
+            Card::regular(RegularKind::Two, Color::Blue),
+            Card::regular(RegularKind::Two, Color::Green),
+            Card::regular(RegularKind::Two, Color::Red),
+            Card::regular(RegularKind::Two, Color::Black),
+            Card::special(SpecialKind::Phoenix),
+        ]);
+        // the twos are a bomb, the phoenix is too valuable
+        assert_eq!(choose_play(&player, None, None, false), Some(vec![1]));
+    }
+
+    #[test]
+    fn test_top_lowest() {
+        let player = player_with(&[
+            Card::regular(RegularKind::King, Color::Red),
+            Card::regular(RegularKind::King, Color::Blue),
+            Card::regular(RegularKind::Seven, Color::Blue),
+            Card::regular(RegularKind::Seven, Color::Green),
+            Card::special(SpecialKind::Phoenix),
+        ]);
+        let trick = Trick::from_cards(vec![
+            Card::regular(RegularKind::Five, Color::Red),
+            Card::regular(RegularKind::Five, Color::Black),
+        ]);
+        assert_eq!(
+            choose_play(&player, Some(&trick), None, false),
+            Some(vec![2, 3])
+        );
+        // the partner is winning
+        assert_eq!(choose_play(&player, Some(&trick), None, true), None);
+        // the wish must be fulfilled, even if the partner is winning
+        assert_eq!(
+            choose_play(&player, Some(&trick), Some(RegularKind::King), true),
+            Some(vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn test_hold_bombs() {
+        let player = player_with(&[
+            Card::regular(RegularKind::Four, Color::Red),
+            Card::regular(RegularKind::Five, Color::Red),
+            Card::regular(RegularKind::Six, Color::Red),
+            Card::regular(RegularKind::Seven, Color::Red),
+            Card::regular(RegularKind::Eight, Color::Red),
+            Card::regular(RegularKind::Ace, Color::Blue),
+        ]);
+        assert_eq!(bomb_cards(&player.get_hand()), vec![0, 1, 2, 3, 4]);
+        let trick = Trick::from_cards(vec![Card::regular(RegularKind::Three, Color::Green)]);
+        assert_eq!(
+            choose_play(&player, Some(&trick), None, false),
+            Some(vec![5])
+        );
+        let trick = Trick::from_cards(vec![Card::special(SpecialKind::Dragon)]);
+        assert_eq!(choose_play(&player, Some(&trick), None, false), None);
+    }
+
+    #[test]
+    fn test_lowest_move() {
+        let player = player_with(&[
+            Card::special(SpecialKind::Phoenix),
+            Card::regular(RegularKind::Nine, Color::Red),
+            Card::regular(RegularKind::Three, Color::Red),
+            Card::regular(RegularKind::Three, Color::Blue),
+        ]);
+        assert_eq!(lowest_move(&player, None, None), Some(vec![2]));
+        assert_eq!(
+            lowest_move(&player, None, Some(RegularKind::Nine)),
+            Some(vec![1])
+        );
+        let trick = Trick::from_cards(vec![
+            Card::regular(RegularKind::Two, Color::Red),
+            Card::regular(RegularKind::Two, Color::Black),
+        ]);
+        assert_eq!(lowest_move(&player, Some(&trick), None), Some(vec![2, 3]));
+        let trick = Trick::from_cards(vec![Card::special(SpecialKind::Dragon)]);
+        assert_eq!(lowest_move(&player, Some(&trick), None), None);
+    }
+}
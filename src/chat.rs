@@ -0,0 +1,137 @@
+use crate::protocol::{ChatData, Payload};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const MAX_LENGTH: usize = 200; // characters of a chat message
+const RATE: usize = 5; // a player may send this many messages and emotes
+const PERIOD: Duration = Duration::from_secs(10); // within this time
+
+pub const EMOTES: [&str; 8] = [
+    "wave", "laugh", "cry", "think", "wow", "angry", "applause", "gg",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct Chat {
+    pub topic: &'static str, // "chat" or "emote"
+    pub partner: bool,       // only for the partner, otherwise for the whole table
+    pub text: String,        // the message or the name of the emote
+}
+
+impl Chat {
+    pub fn payload(&self, seat: usize) -> Payload {
+        Payload::Chat(ChatData {
+            seat,
+            to: if self.partner { "partner" } else { "table" }.to_string(),
+            text: self.text.clone(),
+        })
+    }
+}
+
+pub fn parse(msg: &str) -> Option<Result<Chat, String>> {
+    // "chat <table|partner> <text>" or "emote <table|partner> <emote>",
+    // None if the message is some other command
+    let mut parts = msg.splitn(3, ' ');
+    let topic = match parts.next() {
+        Some("chat") => "chat",
+        Some("emote") => "emote",
+        _ => return None,
+    };
+    let partner = match parts.next() {
+        Some("table") => false,
+        Some("partner") => true,
+        _ => {
+            return Some(Err(format!(
+                "Send it with '{} table' or '{} partner'",
+                topic, topic
+            )))
+        }
+    };
+    let text = parts.next().unwrap_or_default().trim().to_string();
+    if topic == "emote" && !EMOTES.contains(&text.as_str()) {
+        return Some(Err(format!("The emotes are {}", EMOTES.join(", "))));
+    }
+    if text.is_empty() {
+        return Some(Err("The message is empty".to_string()));
+    }
+    if text.chars().count() > MAX_LENGTH {
+        return Some(Err(format!(
+            "Messages can't be longer than {} characters",
+            MAX_LENGTH
+        )));
+    }
+    // line breaks would end the message early
+    if text.chars().any(char::is_control) {
+        return Some(Err("The message contains control characters".to_string()));
+    }
+    Some(Ok(Chat {
+        topic,
+        partner,
+        text,
+    }))
+}
+
+#[derive(Default)]
+pub struct RateLimit {
+    sent: VecDeque<Instant>, // when the recent messages were sent, the oldest first
+}
+
+impl RateLimit {
+    pub fn allow(&mut self, now: Instant) -> bool {
+        // true if another message may be sent now, which is then counted
+        while matches!(self.sent.front(), Some(time) if now.duration_since(*time) >= PERIOD) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() < RATE {
+            self.sent.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("pass"), None);
+        assert_eq!(
+            parse("chat partner  go for it, partner "),
+            Some(Ok(Chat {
+                topic: "chat",
+                partner: true,
+                text: "go for it, partner".to_string()
+            }))
+        );
+        assert_eq!(
+            parse("emote table gg").unwrap().unwrap().payload(2),
+            Payload::Chat(ChatData {
+                seat: 2,
+                to: "table".to_string(),
+                text: "gg".to_string()
+            })
+        );
+        assert!(parse("emote table dance").unwrap().is_err());
+        assert!(parse("chat hello").unwrap().is_err());
+        assert!(parse("chat table").unwrap().is_err());
+        assert!(parse("chat table a\nb").unwrap().is_err());
+        let long = format!("chat table {}", "ä".repeat(MAX_LENGTH));
+        assert!(parse(&long).unwrap().is_ok());
+        assert!(parse(&(long + "!")).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limit = RateLimit::default();
+        let start = Instant::now();
+        for i in 0..RATE {
+            assert!(limit.allow(start + Duration::from_secs(i as u64)));
+        }
+        assert!(!limit.allow(start + Duration::from_secs(9)));
+        // the first message is old enough now, but not the second one
+        assert!(limit.allow(start + PERIOD));
+        assert!(!limit.allow(start + PERIOD));
+    }
+}
@@ -1,7 +1,7 @@
-use crate::deck::{Card, Kind, RegularKind, SpecialKind};
-use std::cmp::{max, min};
+use crate::deck::{Card, Color, Kind, RegularKind, SpecialKind};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Combination {
     Singlet,
     Doublet,
@@ -13,7 +13,151 @@ pub enum Combination {
     StraightFlush,
 }
 
-pub fn find_combination(cards: &[Card]) -> Option<Combination> {
+pub fn legal_tricks(cards: &[Card], trick_to_top: Option<&Trick>) -> Vec<(Vec<usize>, Trick)> {
+    // every trick that can be played with the cards on top of trick_to_top (or as the first
+    // trick if it's None), together with the positions of its cards. this tries all subsets,
+    // a hand is small enough for that
+    let mut tricks = Vec::new();
+    for mask in 1..(1usize << cards.len()) {
+        let positions: Vec<usize> = (0..cards.len()).filter(|n| mask & (1 << n) != 0).collect();
+        let mut trick = Trick::from_cards(positions.iter().map(|n| cards[*n]).collect());
+        if !trick.is_valid() {
+            continue;
+        }
+        trick.set_phoenix_rank(trick_to_top);
+        let legal = match trick_to_top {
+            None => true,
+            Some(other) => trick.tops(other) == Some(true),
+        };
+        if legal {
+            tricks.push((positions, trick));
+        }
+    }
+    tricks
+}
+
+pub fn tricks_with_kind(cards: &[Card], kind: RegularKind) -> Vec<Trick> {
+    // the tricks that can be made of the cards and contain a card of kind: singlets,
+    // doublets, triplets, bombs, fullhouses, straights, straight flushes and stairs. only
+    // the colours of straight flushes matter, so unlike legal_tricks this tries one set
+    // of cards per combination and rank instead of every subset
+    let phoenix = Card::special(SpecialKind::Phoenix);
+    let has_phoenix = cards.contains(&phoenix);
+    // the cards of every rank, from the one (1) to the ace (14)
+    let mut ranks: Vec<Vec<Card>> = vec![Vec::new(); 15];
+    for c in cards {
+        if let Kind::Regular(_) | Kind::Special(SpecialKind::One) = c.kind {
+            ranks[c.rank as usize].push(*c);
+        }
+    }
+    let wished = Card::regular(kind, Color::Black).rank as usize;
+    if ranks[wished].is_empty() {
+        return Vec::new();
+    }
+    let mut tricks: Vec<Vec<Card>> = Vec::new();
+    // n cards of a rank, the last one may be the phoenix
+    let same = |rank: usize, n: usize, with_phoenix: bool| {
+        let real = &ranks[rank];
+        if real.len() >= n {
+            Some(real[..n].to_vec())
+        } else if with_phoenix && has_phoenix && !real.is_empty() && real.len() + 1 == n {
+            Some([&real[..], &[phoenix]].concat())
+        } else {
+            None
+        }
+    };
+    // singlets, doublets, triplets and bombs, which can't contain the phoenix
+    for n in 1..=4 {
+        tricks.extend(same(wished, n, n < 4));
+    }
+    // fullhouses with the wished kind as triplet or as doublet
+    for other in (2..=14).filter(|r| *r != wished) {
+        for (triplet, doublet) in [(wished, other), (other, wished)] {
+            let fullhouse = match (same(triplet, 3, false), same(doublet, 2, true)) {
+                (Some(t), Some(d)) => Some([t, d].concat()),
+                _ => match (same(triplet, 3, true), same(doublet, 2, false)) {
+                    (Some(t), Some(d)) => Some([t, d].concat()),
+                    _ => None,
+                },
+            };
+            tricks.extend(fullhouse);
+        }
+    }
+    // straights, where the phoenix may fill one gap, and straight flushes
+    for len in 5..=14 {
+        for low in (1..=15 - len).filter(|low| (*low..low + len).contains(&wished)) {
+            let window = low..low + len;
+            let straight: Vec<Card> = window
+                .clone()
+                .filter_map(|r| ranks[r].first().copied())
+                .collect();
+            let gaps: Vec<usize> = window.clone().filter(|r| ranks[*r].is_empty()).collect();
+            match gaps[..] {
+                [] => tricks.push(straight),
+                // the phoenix can't stand for the one
+                [gap] if has_phoenix && gap > 1 => {
+                    tricks.push([&straight[..], &[phoenix]].concat())
+                }
+                _ => {}
+            }
+            for color in ranks[wished].iter().map(|c| c.color) {
+                let flush: Vec<Card> = window
+                    .clone()
+                    .filter_map(|r| ranks[r].iter().find(|c| c.color == color).copied())
+                    .collect();
+                if flush.len() == len {
+                    tricks.push(flush);
+                }
+            }
+        }
+    }
+    // stairs, where the phoenix may complete one pair
+    for pairs in 2..=7 {
+        for low in (2..=15 - pairs).filter(|low| (*low..low + pairs).contains(&wished)) {
+            let window = low..low + pairs;
+            let stairs: Vec<Card> = window
+                .clone()
+                .flat_map(|r| ranks[r].iter().take(2).copied())
+                .collect();
+            let short: Vec<usize> = window.filter(|r| ranks[*r].len() < 2).collect();
+            match short[..] {
+                [] => tricks.push(stairs),
+                [r] if has_phoenix && ranks[r].len() == 1 => {
+                    tricks.push([&stairs[..], &[phoenix]].concat())
+                }
+                _ => {}
+            }
+        }
+    }
+    tricks.into_iter().map(Trick::from_cards).collect()
+}
+
+pub fn normalise(cards: &[Card]) -> Option<(Combination, Vec<Card>)> {
+    // find the combination of cards regardless of the order they come in. returns the
+    // combination together with the cards in normalised order: sorted by rank, the phoenix
+    // in the slot it stands for and the triplet of a fullhouse in front of the doublet
+    let phoenix = Card::special(SpecialKind::Phoenix);
+    let mut sorted: Vec<Card> = cards.iter().filter(|c| **c != phoenix).cloned().collect();
+    sorted.sort_by_key(|c| c.rank);
+    if sorted.len() == cards.len() {
+        return check_sorted(&sorted).map(|combination| (combination, order(combination, sorted)));
+    } else if sorted.len() + 1 != cards.len() {
+        // there is only one phoenix
+        return None;
+    }
+    // try every position for the phoenix, starting at the top so that it counts as high as possible
+    for position in (0..=sorted.len()).rev() {
+        let mut candidate = sorted.clone();
+        candidate.insert(position, phoenix);
+        if let Some(combination) = check_sorted(&candidate) {
+            return Some((combination, order(combination, candidate)));
+        }
+    }
+    None
+}
+
+fn check_sorted(cards: &[Card]) -> Option<Combination> {
+    // find the combination of cards that are sorted by rank, with the phoenix in its slot
     match cards.len() {
         0 => None,
         1 => Some(Combination::Singlet),
@@ -34,6 +178,8 @@ pub fn find_combination(cards: &[Card]) -> Option<Combination> {
         4 => {
             if check_bomb(cards) {
                 Some(Combination::Bomb)
+            } else if check_stairs(cards) {
+                Some(Combination::Stairs)
             } else {
                 None
             }
@@ -63,17 +209,27 @@ pub fn find_combination(cards: &[Card]) -> Option<Combination> {
     }
 }
 
+fn order(combination: Combination, mut cards: Vec<Card>) -> Vec<Card> {
+    // sorted cards are already in normalised order, except for fullhouses with a low triplet
+    if combination == Combination::FullHouse && !check_all_equal(&cards[0..3]) {
+        cards.rotate_left(2);
+    }
+    cards
+}
+
 fn check_all_equal(cards: &[Card]) -> bool {
-    // check if all cards are the same according to check_eq
-    cards.iter().all(|c| Card::check_eq(&cards[0], &c))
+    // check if all cards are the same according to check_eq. compare to a card that is not
+    // the phoenix, because the phoenix is equal to every regular card
+    let reference = cards
+        .iter()
+        .find(|c| c.kind != Kind::Special(SpecialKind::Phoenix))
+        .unwrap_or(&cards[0]);
+    cards.iter().all(|c| Card::check_eq(reference, c))
 }
 
 fn check_bomb(cards: &[Card]) -> bool {
     // check if all 4 cards are regular and equal
-    let allregular = cards.iter().all(|c| match c.kind {
-        Kind::Regular(_) => true,
-        _ => false,
-    });
+    let allregular = cards.iter().all(|c| matches!(c.kind, Kind::Regular(_)));
     let allequal = check_all_equal(cards);
     allequal && allregular
 }
@@ -87,43 +243,32 @@ fn check_fullhouse(cards: &[Card]) -> bool {
 }
 
 fn check_straight(cards: &[Card]) -> bool {
-    // check if all cards are consecutive, allowing one and phoenix
-    // for every card in cards check if the next card is one rank above.
-    // if it is, continue, if it isn't return false.
-    // at the end, return true
-    for i in 0..(cards.len() - 1) {
-        // match kind of current card
-        match &cards[i].kind {
-            Kind::Regular(_) => {
-                // match kind of next card
-                match &cards[i + 1].kind {
-                    Kind::Regular(_) => {
-                        if cards[i].rank + 1 != cards[i + 1].rank {
-                            return false;
-                        }
-                    }
-                    Kind::Special(SpecialKind::Phoenix) => {}
-                    _ => return false,
-                }
-            }
+    // check if all cards are consecutive, allowing one and phoenix. the phoenix
+    // stands for the card following the previous one (or preceding the next one
+    // if it's the first card) and may not be used as one, dog or dragon
+    let mut previous: Option<i16> = None;
+    for (i, card) in cards.iter().enumerate() {
+        let rank = match card.kind {
+            Kind::Regular(_) | Kind::Special(SpecialKind::One) => card.rank,
             Kind::Special(SpecialKind::Phoenix) => {
-                // match kind of next kard
-                match &cards[i + 1].kind {
-                    // phoenix must be followed by normal card
-                    Kind::Regular(_) => {}
-                    _ => return false,
-                }
-            }
-            Kind::Special(SpecialKind::One) => {
-                // can be followed by two and phoenix
-                if (cards[i + 1].kind != Kind::Special(SpecialKind::Phoenix))
-                    && (cards[i + 1].kind != Kind::Regular(RegularKind::Two))
-                {
+                let rank = match (previous, cards.get(i + 1)) {
+                    (Some(r), _) => r + 1,
+                    (None, Some(next)) => next.rank - 1,
+                    (None, None) => return false,
+                };
+                if !(2..=14).contains(&rank) {
                     return false;
                 }
+                rank
             }
             _ => return false,
+        };
+        if let Some(r) = previous {
+            if rank != r + 1 {
+                return false;
+            }
         }
+        previous = Some(rank);
     }
     true
 }
@@ -137,34 +282,44 @@ fn check_straightflush(cards: &[Card]) -> bool {
 
 fn check_stairs(cards: &[Card]) -> bool {
     // check if cards consists of consecutive pairs
-    // split every second card into a new vec, check if two vecs are
-    // straights with equal start
-    if cards.len() % 2 != 0 {
+    if !cards.len().is_multiple_of(2) {
         return false;
     }
-    // check if first two elements are the same
-    if !Card::check_eq(&cards[0], &cards[1]) {
-        return false;
+    let mut previous: Option<i16> = None;
+    for pair in cards.chunks(2) {
+        if !check_all_equal(pair) {
+            return false;
+        }
+        let rank = find_nonphoenix_rank(pair);
+        if let Some(r) = previous {
+            if rank != r + 1 {
+                return false;
+            }
+        }
+        previous = Some(rank);
     }
+    true
+}
 
-    let mut straight1: Vec<Card> = Vec::new();
-    let mut straight2: Vec<Card> = Vec::new();
-    for card in cards.iter().step_by(2) {
-        straight1.push(*card);
-    }
-    for card in cards[1..].iter().step_by(2) {
-        straight2.push(*card);
+fn find_nonphoenix_rank(cards: &[Card]) -> i16 {
+    // in a set of cards, return the first rank that is not a phoenix
+    for card in cards {
+        if card.kind != Kind::Special(SpecialKind::Phoenix) {
+            return card.rank;
+        }
     }
-    check_straight(&straight1) && check_straight(&straight2)
+    0
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Trick {
     // implements the combination of cards that is going to be played
     // this may be a valid combination or not (tricks of invalid combinations
     // may not be played)
     pub combination: Option<Combination>,
     pub cards: Vec<Card>, // it must be possible to add and remove cards
+    // a phoenix played as a singlet is worth half a rank more than the card it was played on
+    pub phoenix_rank: Option<f32>,
 }
 
 impl Trick {
@@ -172,12 +327,56 @@ impl Trick {
         Trick {
             combination: None,
             cards: Vec::new(),
+            phoenix_rank: None,
+        }
+    }
+
+    pub fn from_cards(cards: Vec<Card>) -> Trick {
+        let mut trick = Trick::new();
+        match normalise(&cards) {
+            Some((combination, normalised)) => {
+                trick.combination = Some(combination);
+                trick.cards = normalised;
+            }
+            None => trick.cards = cards,
+        }
+        trick
+    }
+
+    pub fn set_phoenix_rank(&mut self, previous: Option<&Trick>) {
+        // call this once the trick is played on top of previous (None if it was led)
+        if self.combination != Some(Combination::Singlet)
+            || self.cards[0].kind != Kind::Special(SpecialKind::Phoenix)
+        {
+            return;
+        }
+        let rank = match previous {
+            Some(trick) if trick.combination == Some(Combination::Singlet) => {
+                trick.singlet_rank() + 0.5
+            }
+            _ => 1.5,
+        };
+        // the phoenix is at least 1.5, even if it's played on the one
+        self.phoenix_rank = Some(rank.max(1.5));
+    }
+
+    fn singlet_rank(&self) -> f32 {
+        match self.phoenix_rank {
+            Some(rank) => rank,
+            None => self.cards[0].rank as f32,
         }
     }
 
     pub fn push(&mut self, element: Card) {
         self.cards.push(element);
-        self.combination = find_combination(&self.cards);
+        // valid tricks keep their cards in normalised order
+        match normalise(&self.cards) {
+            Some((combination, cards)) => {
+                self.combination = Some(combination);
+                self.cards = cards;
+            }
+            None => self.combination = None,
+        }
     }
 
     pub fn points(&self) -> i16 {
@@ -185,89 +384,44 @@ impl Trick {
     }
 
     pub fn is_valid(&self) -> bool {
-        self.combination != None
+        self.combination.is_some()
     }
 
-    fn total_rank(&self) -> i16 {
-        // compute the sum of all ranks
-        // if there is no phoenix, this is easy enough
-        if Trick::no_phoenix(&self.cards) {
-            return self.cards.iter().fold(0, |acc, c| acc + c.rank);
-        } else {
-            // rank of the phoenix depends on the kind of combination
-            if self.combination == Some(Combination::FullHouse) {
-                // split cases between 2 + 3 fullhouses and 3 + 2
-                let first_two = check_all_equal(&self.cards[0..2]);
-                let last_three = check_all_equal(&self.cards[2..5]);
-                if first_two && last_three {
-                    // find the phoenix
-                    // if the phoenix is in the last three, its rank is ambiguous
-                    // in this case, take the phoenix to be the higher ranked card
-                    if Trick::no_phoenix(&self.cards[0..2]) {
-                        let doublet_rank = 2 * min(
-                            self.cards[0].rank,
-                            Trick::find_nonphoenix_rank(&self.cards[2..5]),
-                        );
-                        let triplet_rank = 3 * max(
-                            self.cards[0].rank,
-                            Trick::find_nonphoenix_rank(&self.cards[2..5]),
-                        );
-                        return doublet_rank + triplet_rank;
-                    } else {
-                        // in the other case the phoenix forms the doublet
-                        return 2 * Trick::find_nonphoenix_rank(&self.cards[0..2])
-                            + 3 * self.cards[2].rank;
-                    }
-                } else {
-                    // the fullhouse is 3 + 2
-                    // find phoenix again
-                    if Trick::no_phoenix(&self.cards[0..3]) {
-                        // the phoenix builds the doublet
-                        return 2 * Trick::find_nonphoenix_rank(&self.cards[3..5])
-                            + 3 * self.cards[0].rank;
-                    } else {
-                        // phoenix is ambiguous again
-                        let doublet_rank = 2 * min(
-                            Trick::find_nonphoenix_rank(&self.cards[0..3]),
-                            self.cards[3].rank,
-                        );
-                        let triplet_rank = 3 * max(
-                            Trick::find_nonphoenix_rank(&self.cards[0..3]),
-                            self.cards[3].rank,
-                        );
-                        return doublet_rank + triplet_rank;
-                    }
-                }
-            } else if self.combination == Some(Combination::Doublet) {
-                return 2 * Trick::find_nonphoenix_rank(&self.cards);
-            } else if self.combination == Some(Combination::Triplet) {
-                return 3 * Trick::find_nonphoenix_rank(&self.cards);
-            }
-            // don't care about straights and signglets, they are covered in tops without the need of rank
-            return 0;
-        }
+    pub fn is_bomb(&self) -> bool {
+        self.combination == Some(Combination::Bomb)
+            || self.combination == Some(Combination::StraightFlush)
     }
 
-    fn no_phoenix(cards: &[Card]) -> bool {
-        // check if there is no phoenix in cards
-        return cards
-            .iter()
-            .all(|c| c.kind != Kind::Special(SpecialKind::Phoenix));
+    pub fn contains_kind(&self, kind: Kind) -> bool {
+        self.cards.iter().any(|c| c.kind == kind)
     }
 
-    fn find_nonphoenix_rank(cards: &[Card]) -> i16 {
-        // in a set of cards, return the first rank that is not a phoenix
-        for card in cards {
-            if card.kind != Kind::Special(SpecialKind::Phoenix) {
-                return card.rank;
-            }
+    pub fn rank(&self) -> i16 {
+        // the rank that decides between two tricks of the same combination. this relies
+        // on the normalised order of the cards
+        match self.combination {
+            Some(Combination::FullHouse) => find_nonphoenix_rank(&self.cards[0..3]),
+            Some(Combination::Straight) | Some(Combination::StraightFlush) => self.top_rank(1),
+            Some(Combination::Stairs) => self.top_rank(2),
+            _ => find_nonphoenix_rank(&self.cards),
         }
-        return 0;
+    }
+
+    fn top_rank(&self, width: usize) -> i16 {
+        // rank of the highest card (width 1) or pair (width 2) of a straight or stairs,
+        // computed from the position of the first card that is not the phoenix
+        let (i, card) = self
+            .cards
+            .iter()
+            .enumerate()
+            .find(|(_, c)| c.kind != Kind::Special(SpecialKind::Phoenix))
+            .unwrap();
+        card.rank - (i / width) as i16 + (self.cards.len() / width) as i16 - 1
     }
 
     pub fn tops(&self, other: &Self) -> Option<bool> {
         // check if a trick beats another, returns None if combinations are not compatible
-        if self.combination == None || other.combination == None {
+        if self.combination.is_none() || other.combination.is_none() {
             return None;
         }
         let thiscombination = self.combination.unwrap(); // extract the value from Some()
@@ -285,48 +439,41 @@ impl Trick {
         // from now on either one of the combinations is bomb or flush or the combinations match
         // go through all possibilities
         if thiscombination == Combination::StraightFlush {
-            // beats everything other than a flush and only flush if it's higher or longer
-            return Some(
+            // beats everything other than a flush and only flush if it's longer or higher
+            Some(
                 othercombination != Combination::StraightFlush
                     || self.cards.len() > other.cards.len()
-                    || self.cards[0].rank > other.cards[0].rank,
-            );
+                    || (self.cards.len() == other.cards.len() && self.rank() > other.rank()),
+            )
         } else if thiscombination == Combination::Bomb {
             // beats everything except flushs and higher bombs
-            return Some(
-                (othercombination == Combination::Bomb && self.cards[0].rank > other.cards[0].rank)
-                    || othercombination != Combination::StraightFlush,
-            );
-        } else if thiscombination == Combination::Straight || thiscombination == Combination::Stairs {
-            // tops if it is longer or higher
-            if self.cards.len() > other.cards.len() {
-                return Some(true);
-            } else if self.cards.len() < other.cards.len() {
-                return Some(false);
-            }
-            // if they are equally long, compare starting or end rank (avoid phoenix)
-            else if self.cards[0].kind == Kind::Special(SpecialKind::Phoenix)
-                || other.cards[0].kind == Kind::Special(SpecialKind::Phoenix)
-            {
-                return Some(
-                    self.cards[self.cards.len()].rank > other.cards[other.cards.len()].rank,
-                );
+            Some(
+                (othercombination == Combination::Bomb && self.rank() > other.rank())
+                    || (othercombination != Combination::Bomb
+                        && othercombination != Combination::StraightFlush),
+            )
+        } else if thiscombination == Combination::Straight || thiscombination == Combination::Stairs
+        {
+            // only tricks of the same length can be compared
+            if self.cards.len() != other.cards.len() {
+                None
             } else {
-                return Some(self.cards[0].rank > other.cards[0].rank);
+                Some(self.rank() > other.rank())
             }
         } else if thiscombination == Combination::Singlet {
             if self.cards[0].kind == Kind::Special(SpecialKind::Dragon) {
-                return Some(true);
-            } else if self.cards[0].kind == Kind::Special(SpecialKind::Phoenix)
-                && other.cards[0].kind != Kind::Special(SpecialKind::Dragon)
-            {
-                return Some(true);
+                Some(true)
+            } else if other.cards[0].kind == Kind::Special(SpecialKind::Dragon) {
+                Some(false)
+            } else if self.cards[0].kind == Kind::Special(SpecialKind::Phoenix) {
+                // the phoenix tops every other singlet, its rank is set once it's played
+                Some(true)
             } else {
-                return Some(self.cards[0].rank > other.cards[0].rank);
+                Some(self.cards[0].rank as f32 > other.singlet_rank())
             }
         } else {
             // tops if it is higher ranked
-            return Some(self.total_rank() > other.total_rank());
+            Some(self.rank() > other.rank())
         }
     }
 }
@@ -334,7 +481,12 @@ impl Trick {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::deck::Color;
+    use crate::deck::Deck;
+    use strum::IntoEnumIterator;
+
+    fn find_combination(cards: &[Card]) -> Option<Combination> {
+        normalise(cards).map(|(combination, _)| combination)
+    }
 
     #[test]
     fn test_find_doublet() {
@@ -473,6 +625,73 @@ mod tests {
         assert_eq!(find_combination(&hand), None);
     }
 
+    #[test]
+    fn test_find_unsorted() {
+        // straight with the phoenix filling a gap
+        let hand = [
+            Card::regular(RegularKind::Seven, Color::Blue),
+            Card::special(SpecialKind::Phoenix),
+            Card::regular(RegularKind::Four, Color::Red),
+            Card::special(SpecialKind::One),
+            Card::regular(RegularKind::Two, Color::Blue),
+            Card::regular(RegularKind::Six, Color::Blue),
+            Card::regular(RegularKind::Five, Color::Green),
+        ];
+        assert_eq!(find_combination(&hand), Some(Combination::Straight));
+        // stairs with the phoenix completing the lowest pair
+        let hand = [
+            Card::regular(RegularKind::Jack, Color::Black),
+            Card::regular(RegularKind::Ten, Color::Black),
+            Card::regular(RegularKind::Nine, Color::Green),
+            Card::regular(RegularKind::Jack, Color::Red),
+            Card::special(SpecialKind::Phoenix),
+            Card::regular(RegularKind::Ten, Color::Green),
+        ];
+        assert_eq!(find_combination(&hand), Some(Combination::Stairs));
+        // the phoenix can't stand for a card that is not next to the others
+        let hand = [
+            Card::regular(RegularKind::Two, Color::Black),
+            Card::special(SpecialKind::Phoenix),
+            Card::regular(RegularKind::Six, Color::Green),
+        ];
+        assert_eq!(find_combination(&hand), None);
+    }
+
+    #[test]
+    fn test_normalised_order() {
+        let phoenix = Card::special(SpecialKind::Phoenix);
+        let blackfive = Card::regular(RegularKind::Five, Color::Black);
+        let bluefive = Card::regular(RegularKind::Five, Color::Blue);
+        let redqueen = Card::regular(RegularKind::Queen, Color::Red);
+        let greenqueen = Card::regular(RegularKind::Queen, Color::Green);
+        // the phoenix joins the higher pair and the triplet goes first
+        let trick = Trick::from_cards(vec![redqueen, blackfive, phoenix, bluefive, greenqueen]);
+        assert_eq!(trick.combination, Some(Combination::FullHouse));
+        assert_eq!(
+            trick.cards,
+            vec![redqueen, greenqueen, phoenix, blackfive, bluefive]
+        );
+        // a straight without gap gets the phoenix on top
+        let trick = Trick::from_cards(vec![
+            Card::regular(RegularKind::Ten, Color::Red),
+            Card::regular(RegularKind::Eight, Color::Red),
+            phoenix,
+            Card::regular(RegularKind::Nine, Color::Green),
+            Card::regular(RegularKind::Seven, Color::Red),
+        ]);
+        assert_eq!(trick.cards[4], phoenix);
+        // unless it ends with the ace
+        let trick = Trick::from_cards(vec![
+            Card::regular(RegularKind::Ace, Color::Red),
+            Card::regular(RegularKind::King, Color::Red),
+            phoenix,
+            Card::regular(RegularKind::Queen, Color::Green),
+            Card::regular(RegularKind::Jack, Color::Red),
+        ]);
+        assert_eq!(trick.combination, Some(Combination::Straight));
+        assert_eq!(trick.cards[0], phoenix);
+    }
+
     #[test]
     fn test_trick() {
         let mut trick = Trick::new();
@@ -489,6 +708,43 @@ mod tests {
         assert_eq!(trick.combination, Some(Combination::Doublet));
     }
 
+    #[test]
+    fn test_tops_phoenix_singlet() {
+        let mut seven = Trick::new();
+        seven.push(Card::regular(RegularKind::Seven, Color::Red));
+        let mut phoenix = Trick::new();
+        phoenix.push(Card::special(SpecialKind::Phoenix));
+        assert_eq!(phoenix.tops(&seven), Some(true));
+        phoenix.set_phoenix_rank(Some(&seven));
+        assert_eq!(phoenix.phoenix_rank, Some(7.5));
+        // another seven doesn't top the phoenix, an eight does
+        let mut seven = Trick::new();
+        seven.push(Card::regular(RegularKind::Seven, Color::Blue));
+        assert_eq!(seven.tops(&phoenix), Some(false));
+        let mut eight = Trick::new();
+        eight.push(Card::regular(RegularKind::Eight, Color::Blue));
+        assert_eq!(eight.tops(&phoenix), Some(true));
+        // the phoenix never tops the dragon
+        let mut dragon = Trick::new();
+        dragon.push(Card::special(SpecialKind::Dragon));
+        assert_eq!(phoenix.tops(&dragon), Some(false));
+        assert_eq!(dragon.tops(&phoenix), Some(true));
+    }
+
+    #[test]
+    fn test_lead_phoenix_singlet() {
+        let mut phoenix = Trick::new();
+        phoenix.push(Card::special(SpecialKind::Phoenix));
+        phoenix.set_phoenix_rank(None);
+        assert_eq!(phoenix.phoenix_rank, Some(1.5));
+        let mut two = Trick::new();
+        two.push(Card::regular(RegularKind::Two, Color::Green));
+        assert_eq!(two.tops(&phoenix), Some(true));
+        let mut one = Trick::new();
+        one.push(Card::special(SpecialKind::One));
+        assert_eq!(one.tops(&phoenix), Some(false));
+    }
+
     #[test]
     fn test_tops_bomb() {
         let mut bomb = Trick::new();
@@ -546,7 +802,6 @@ mod tests {
         trick1.push(bluetwo);
         trick1.push(blacktwo);
         trick1.push(greentwo);
-        println!("trick1 rank {}", trick1.total_rank());
         // construct a bigger fullhouse with phoenix
         let mut trick2 = Trick::new();
         let bluesix = Card::regular(RegularKind::Six, Color::Blue);
@@ -559,7 +814,6 @@ mod tests {
         trick2.push(phoenix);
         trick2.push(blacktwo);
         trick2.push(greentwo);
-        println!("trick2 rank {}", trick2.total_rank());
         assert_eq!(trick2.tops(&trick1), Some(true));
     }
 
@@ -578,8 +832,51 @@ mod tests {
         trick2.push(Card::regular(RegularKind::Five, Color::Black));
         trick2.push(Card::regular(RegularKind::Six, Color::Black));
         trick2.push(Card::regular(RegularKind::Seven, Color::Black));
+        // a longer straight doesn't top a shorter one
+        assert_eq!(trick1.tops(&trick2), None);
+        assert_eq!(trick2.tops(&trick1), None);
+        let trick3 = Trick::from_cards(trick2.cards[1..].to_vec());
+        assert_eq!(trick1.tops(&trick3), Some(true));
+        assert_eq!(trick3.tops(&trick1), Some(false));
+    }
+
+    #[test]
+    fn test_tops_straights_phoenix() {
+        // 7 8 9 10 phoenix is a straight up to the jack
+        let trick1 = Trick::from_cards(vec![
+            Card::special(SpecialKind::Phoenix),
+            Card::regular(RegularKind::Seven, Color::Black),
+            Card::regular(RegularKind::Eight, Color::Black),
+            Card::regular(RegularKind::Nine, Color::Red),
+            Card::regular(RegularKind::Ten, Color::Black),
+        ]);
+        let trick2 = Trick::from_cards(vec![
+            Card::regular(RegularKind::Seven, Color::Green),
+            Card::regular(RegularKind::Eight, Color::Green),
+            Card::regular(RegularKind::Nine, Color::Blue),
+            Card::regular(RegularKind::Ten, Color::Green),
+            Card::regular(RegularKind::Jack, Color::Green),
+        ]);
+        assert_eq!(trick2.tops(&trick1), Some(false));
         assert_eq!(trick1.tops(&trick2), Some(false));
-        assert_eq!(trick2.tops(&trick1), Some(true));
+    }
+
+    #[test]
+    fn test_tops_bombs() {
+        let fives = Trick::from_cards(vec![
+            Card::regular(RegularKind::Five, Color::Black),
+            Card::regular(RegularKind::Five, Color::Blue),
+            Card::regular(RegularKind::Five, Color::Red),
+            Card::regular(RegularKind::Five, Color::Green),
+        ]);
+        let kings = Trick::from_cards(vec![
+            Card::regular(RegularKind::King, Color::Black),
+            Card::regular(RegularKind::King, Color::Blue),
+            Card::regular(RegularKind::King, Color::Red),
+            Card::regular(RegularKind::King, Color::Green),
+        ]);
+        assert_eq!(kings.tops(&fives), Some(true));
+        assert_eq!(fives.tops(&kings), Some(false));
     }
 
     #[test]
@@ -591,7 +888,7 @@ mod tests {
         trick1.push(Card::regular(RegularKind::Six, Color::Red));
         trick1.push(Card::regular(RegularKind::Seven, Color::Blue));
         trick1.push(Card::regular(RegularKind::Seven, Color::Red));
-        // construct longer but lower stairs
+        // construct longer but lower stairs, which can't be compared
         let mut trick2 = Trick::new();
         trick2.push(Card::regular(RegularKind::Two, Color::Blue));
         trick2.push(Card::regular(RegularKind::Two, Color::Red));
@@ -601,7 +898,88 @@ mod tests {
         trick2.push(Card::regular(RegularKind::Four, Color::Red));
         trick2.push(Card::regular(RegularKind::Five, Color::Blue));
         trick2.push(Card::regular(RegularKind::Five, Color::Red));
-        assert_eq!(trick1.tops(&trick2), Some(false));
-        assert_eq!(trick2.tops(&trick1), Some(true));
+        assert_eq!(trick1.tops(&trick2), None);
+        assert_eq!(trick2.tops(&trick1), None);
+        let trick3 = Trick::from_cards(trick2.cards[..6].to_vec());
+        assert_eq!(trick1.tops(&trick3), Some(true));
+        assert_eq!(trick3.tops(&trick1), Some(false));
+    }
+
+    #[test]
+    fn test_legal_tricks_lead() {
+        let hand = vec![
+            Card::regular(RegularKind::Three, Color::Red),
+            Card::regular(RegularKind::Three, Color::Blue),
+            Card::special(SpecialKind::Phoenix),
+        ];
+        let tricks = legal_tricks(&hand, None);
+        let positions: Vec<Vec<usize>> = tricks.iter().map(|(p, _)| p.clone()).collect();
+        // three singlets, three pairs (two of them with the phoenix) and a triplet
+        assert_eq!(tricks.len(), 7);
+        assert!(positions.contains(&vec![1, 2]));
+        assert!(positions.contains(&vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn test_legal_tricks_on_top() {
+        let hand = vec![
+            Card::regular(RegularKind::Six, Color::Green),
+            Card::regular(RegularKind::Seven, Color::Green),
+            Card::regular(RegularKind::Eight, Color::Green),
+            Card::regular(RegularKind::Nine, Color::Green),
+            Card::regular(RegularKind::Ten, Color::Green),
+            Card::regular(RegularKind::Four, Color::Red),
+            Card::special(SpecialKind::Phoenix),
+        ];
+        let pair = Trick::from_cards(vec![
+            Card::regular(RegularKind::Five, Color::Red),
+            Card::regular(RegularKind::Five, Color::Black),
+        ]);
+        let tricks = legal_tricks(&hand, Some(&pair));
+        // the phoenix makes a pair with any card above five, the straight flush is a bomb
+        let pairs = tricks
+            .iter()
+            .filter(|(_, t)| t.combination == Some(Combination::Doublet))
+            .count();
+        assert_eq!(pairs, 5);
+        assert!(tricks.iter().any(|(p, _)| p == &vec![0, 1, 2, 3, 4]));
+        assert!(tricks.iter().all(|(_, t)| t.tops(&pair) == Some(true)));
+        let dragon = Trick::from_cards(vec![Card::special(SpecialKind::Dragon)]);
+        // only bombs top the dragon
+        assert!(legal_tricks(&hand, Some(&dragon))
+            .iter()
+            .all(|(_, t)| t.is_bomb()));
+    }
+
+    #[test]
+    fn test_tricks_with_kind() {
+        // a wish can be fulfilled exactly if one of the legal tricks contains the kind
+        for seed in 0..6 {
+            let mut deck = Deck::new();
+            deck.shuffle(seed);
+            let hands = deck.deal();
+            // one trick of every combination from another hand, the lowest and the highest
+            let mut tops: Vec<Trick> = Vec::new();
+            for (_, trick) in legal_tricks(&hands[1], None) {
+                let combination = trick.combination;
+                match tops.iter().position(|t| t.combination == combination) {
+                    None => tops.extend(vec![trick.clone(), trick]),
+                    Some(i) if trick.tops(&tops[i + 1]) == Some(true) => tops[i + 1] = trick,
+                    Some(_) => {}
+                }
+            }
+            for top in &tops {
+                let legal = legal_tricks(&hands[0], Some(top));
+                for kind in RegularKind::iter() {
+                    let expected = legal
+                        .iter()
+                        .any(|(_, t)| t.contains_kind(Kind::Regular(kind)));
+                    let found = tricks_with_kind(&hands[0], kind)
+                        .iter()
+                        .any(|t| t.tops(top) == Some(true));
+                    assert_eq!(found, expected, "{:?} on {:?} in {:?}", kind, top, hands[0]);
+                }
+            }
+        }
     }
 }
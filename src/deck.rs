@@ -1,11 +1,15 @@
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
-use std::string::ToString;
+use std::str::FromStr;
 use strum::IntoEnumIterator; // iterate over static enum
-use strum_macros::{Display, EnumIter};
+use strum_macros::{Display, EnumIter, EnumString};
 
-#[derive(Debug, Display, PartialEq, Eq, Copy, Clone, Hash, EnumIter)]
+#[derive(
+    Debug, Display, PartialEq, Eq, Copy, Clone, Hash, EnumIter, EnumString, Serialize, Deserialize,
+)]
 pub enum RegularKind {
     Two,
     Three,
@@ -22,7 +26,7 @@ pub enum RegularKind {
     Ace,
 }
 
-#[derive(Debug, Display, PartialEq, Eq, Copy, Clone, Hash, EnumIter)]
+#[derive(Debug, Display, PartialEq, Eq, Copy, Clone, Hash, EnumIter, EnumString)]
 pub enum SpecialKind {
     Dragon,
     Phoenix,
@@ -36,7 +40,7 @@ pub enum Kind {
     Regular(RegularKind),
 }
 
-#[derive(Debug, Display, PartialEq, Eq, Copy, Clone, Hash)]
+#[derive(Debug, Display, PartialEq, Eq, Copy, Clone, Hash, EnumString)]
 pub enum Color {
     Black,
     Blue,
@@ -55,12 +59,44 @@ pub struct Card {
 impl fmt::Display for Card {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.kind {
-            Kind::Regular(k) => write!(f, "{} {}", self.color.unwrap().to_string(), k.to_string()),
-            Kind::Special(k) => write!(f, "{}", k.to_string()),
+            Kind::Regular(k) => write!(f, "{} {}", self.color.unwrap(), k),
+            Kind::Special(k) => write!(f, "{}", k),
         }
     }
 }
 
+impl FromStr for Card {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Card, String> {
+        // the inverse of Display, e.g. "Red Five" or "Dragon"
+        let error = || format!("'{}' is not a card", s);
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words[..] {
+            [color, kind] => Ok(Card::regular(
+                kind.parse().map_err(|_| error())?,
+                color.parse().map_err(|_| error())?,
+            )),
+            [kind] => Ok(Card::special(kind.parse().map_err(|_| error())?)),
+            _ => Err(error()),
+        }
+    }
+}
+
+// cards are written by their name, e.g. in replays
+impl Serialize for Card {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Card {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Card, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(de::Error::custom)
+    }
+}
+
 impl Card {
     pub fn regular(kind: RegularKind, color: Color) -> Card {
         // returns a new regular card
@@ -76,8 +112,8 @@ impl Card {
         Card {
             kind: Kind::Regular(kind),
             color: Some(color),
-            rank: rank,
-            value: value,
+            rank,
+            value,
         }
     }
 
@@ -86,6 +122,7 @@ impl Card {
         let mut rank = 0;
         let mut value = 0;
         match &kind {
+            SpecialKind::One => rank = 1,
             SpecialKind::Phoenix => {
                 rank = 0;
                 value = -25;
@@ -94,14 +131,14 @@ impl Card {
                 rank = 14;
                 value = 25
             }
-            _ => {} // one and dog have rank and value 0
+            _ => {} // dog has rank and value 0
         };
         // return a card
         Card {
             kind: Kind::Special(kind),
             color: None,
-            rank: rank,
-            value: value,
+            rank,
+            value,
         }
     }
 
@@ -144,8 +181,9 @@ impl Deck {
         Deck { cards: deck }
     }
 
-    pub fn shuffle(&mut self) {
-        let mut rng = thread_rng();
+    pub fn shuffle(&mut self, seed: u64) {
+        // the same seed gives the same order, as long as the deck was in the same order before
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
         self.cards.shuffle(&mut rng);
     }
 
@@ -190,7 +228,7 @@ mod tests {
 
     #[test]
     fn test_deal() {
-        let mut deck = Deck::new();
+        let deck = Deck::new();
         let hands = deck.deal();
         // deck shouldn't change
         assert_eq!(deck.cards.len(), 56);
@@ -200,6 +238,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_card() {
+        // every card can be parsed from its name
+        for card in Deck::new().cards {
+            assert_eq!(card.to_string().parse(), Ok(card));
+        }
+        assert_eq!(
+            "Green Ten".parse(),
+            Ok(Card::regular(RegularKind::Ten, Color::Green))
+        );
+        assert!("Purple Ten".parse::<Card>().is_err());
+        assert!("Red".parse::<Card>().is_err());
+        assert!("Red Ten Dragon".parse::<Card>().is_err());
+    }
+
+    #[test]
+    fn test_shuffle_seed() {
+        let mut deck = Deck::new();
+        deck.shuffle(42);
+        let mut same = Deck::new();
+        same.shuffle(42);
+        assert_eq!(deck.cards, same.cards);
+        let mut other = Deck::new();
+        other.shuffle(43);
+        assert_ne!(deck.cards, other.cards);
+        assert_ne!(deck.cards, Deck::new().cards);
+    }
+
     #[test]
     fn test_check_eq() {
         // check two equals
@@ -0,0 +1,832 @@
+use crate::accounts::{self, Accounts};
+use crate::bot;
+use crate::chat::{Chat, RateLimit};
+use crate::protocol::{Login, Message, Payload, Protocol, TableData};
+use crate::rules::RuleSet;
+use crate::snapshot::{self, Restored};
+use crate::stats::Stats;
+use crate::tichuserver::TichuConnection;
+use crate::timer::{self, Limits};
+use crate::tournament::{Entry, Tournament};
+use crate::transport::Client;
+use log::{info, warn};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::collections::BTreeMap;
+use std::iter;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// number of players in the leaderboard
+const LEADERBOARD_SIZE: usize = 10;
+const MAX_TEAM_NAME: usize = 20;
+
+// messages for the seated players. they are sent once the tables are unlocked, so that a slow
+// connection doesn't hold up the whole lobby
+type Outbox = Vec<(Client, Message)>;
+
+struct Seat {
+    username: String,
+    stream: Client,                 // used to send messages to the player sitting here
+    token: String, // lets the player take the seat again after losing the connection
+    bot: Option<Receiver<Message>>, // messages for a bot, until the bot starts playing
+    ready: bool,   // the player agrees with the seating, bots always do
+}
+
+struct Table {
+    seats: [Option<Seat>; 4],
+    game: Option<Arc<TichuConnection>>, // None until the game is started
+    seed: Option<u64>,                  // for the deals, chosen when the table is created
+    team_names: [Option<String>; 2],    // chosen by the players, for the teams 0,2 and 1,3
+    swaps: [Option<usize>; 4],          // the seat each player wants to swap with
+}
+
+impl Table {
+    fn new(seed: Option<u64>) -> Table {
+        Table {
+            seats: [None, None, None, None],
+            game: None,
+            seed,
+            team_names: [None, None],
+            swaps: [None; 4],
+        }
+    }
+
+    fn seat_bot(&mut self, seat: usize) {
+        // bots are named after their seat, unless a bot that has moved already has that name.
+        // players can't have these names
+        let taken = self.usernames();
+        let username = iter::once(seat)
+            .chain(0..)
+            .map(|n| format!("bot{}", n))
+            .find(|name| !taken.contains(&Some(name.clone())))
+            .unwrap();
+        let (sender, inbox) = channel();
+        self.seats[seat] = Some(Seat {
+            username,
+            stream: Client::bot(sender),
+            token: new_token(),
+            bot: Some(inbox),
+            ready: true,
+        });
+    }
+
+    fn streams(&self) -> [Mutex<Client>; 4] {
+        // the connections for the game, all seats must be taken
+        [0, 1, 2, 3].map(|i| Mutex::new(self.seats[i].as_ref().unwrap().stream.clone()))
+    }
+
+    fn saved_seats(&self) -> [snapshot::Seat; 4] {
+        // what the snapshots of the game need to know about the seats
+        [0, 1, 2, 3].map(|i| {
+            let seat = self.seats[i].as_ref().unwrap();
+            snapshot::Seat {
+                username: seat.username.clone(),
+                token: seat.token.clone(),
+                bot: seat.stream.is_bot(),
+            }
+        })
+    }
+
+    fn spawn_bots(&mut self, game: &Arc<TichuConnection>) {
+        for (i, seat) in self.seats.iter_mut().enumerate() {
+            if let Some(inbox) = seat.as_mut().unwrap().bot.take() {
+                bot::spawn(game.clone(), i, inbox);
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.seats.iter().all(|s| s.is_some())
+    }
+
+    fn is_ready(&self) -> bool {
+        self.seats
+            .iter()
+            .all(|s| matches!(s, Some(seat) if seat.ready))
+    }
+
+    fn seat_of(&self, token: &str) -> Option<usize> {
+        self.seats
+            .iter()
+            .position(|s| matches!(s, Some(seat) if seat.token == token))
+    }
+
+    fn team_names(&self) -> Vec<String> {
+        // a team without a name is called after its players, e.g. "alice & carol"
+        (0..2)
+            .map(|team| match &self.team_names[team] {
+                Some(name) => name.clone(),
+                None => {
+                    let names: Vec<String> = [team, team + 2]
+                        .iter()
+                        .map(|&i| match &self.seats[i] {
+                            Some(seat) => seat.username.clone(),
+                            None => "-".to_string(),
+                        })
+                        .collect();
+                    names.join(" & ")
+                }
+            })
+            .collect()
+    }
+
+    fn seating_changed(&mut self) -> Outbox {
+        // everyone has to agree with the new seating again, swap requests and team names are
+        // forgotten
+        for seat in self.seats.iter_mut().flatten() {
+            seat.ready = seat.stream.is_bot();
+        }
+        self.swaps = [None; 4];
+        self.team_names = [None, None];
+        let mut outbox = self.push_to_seats("seats", Payload::Seats(self.usernames()));
+        outbox.extend(self.push_to_seats("teams", Payload::Teams(self.team_names())));
+        outbox
+    }
+
+    fn usernames(&self) -> Vec<Option<String>> {
+        // usernames of the seated players, None for free seats
+        self.seats
+            .iter()
+            .map(|s| s.as_ref().map(|seat| seat.username.clone()))
+            .collect()
+    }
+
+    fn chat_messages(&self, seat: usize, chat: &Chat) -> Outbox {
+        // the sender gets their own message as well
+        let message = Message::Push(chat.topic, chat.payload(seat));
+        self.seats
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !chat.partner || i % 2 == seat % 2)
+            .filter_map(|(_, s)| s.as_ref())
+            .map(|s| (s.stream.clone(), message.clone()))
+            .collect()
+    }
+
+    fn push_to_seats(&self, topic: &'static str, payload: Payload) -> Outbox {
+        let message = Message::Push(topic, payload);
+        self.seats
+            .iter()
+            .flatten()
+            .map(|seat| (seat.stream.clone(), message.clone()))
+            .collect()
+    }
+
+    fn push_to_seat(&self, seat: usize, topic: &'static str, payload: Payload) -> Outbox {
+        self.seats[seat]
+            .iter()
+            .map(|seat| (seat.stream.clone(), Message::Push(topic, payload.clone())))
+            .collect()
+    }
+}
+
+fn deliver(outbox: Outbox) {
+    for (mut stream, message) in outbox {
+        stream.send(&message);
+    }
+}
+
+pub struct Visitor {
+    // a connection that is in the lobby or sitting at a table
+    pub username: String,
+    stream: Client,
+    // name of the table and token of the seat, the seat may change until the game starts
+    seat: Option<(String, String)>,
+    watching: Option<(String, usize)>, // name of the table and id of the spectator
+    chat_limit: RateLimit,
+}
+
+impl Visitor {
+    pub fn new(username: String, stream: Client) -> Visitor {
+        Visitor {
+            username,
+            stream,
+            seat: None,
+            watching: None,
+            chat_limit: RateLimit::default(),
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.stream.protocol
+    }
+
+    fn seat_at(&mut self, tables: &BTreeMap<String, Table>) -> Option<(String, usize)> {
+        // the table and the index of the visitor's seat. once the table is closed, the visitor
+        // is back in the lobby
+        let (name, token) = self.seat.clone()?;
+        match tables.get(&name).and_then(|table| table.seat_of(&token)) {
+            Some(seat) => Some((name, seat)),
+            None => {
+                self.seat = None;
+                None
+            }
+        }
+    }
+
+    fn answer_ok(&mut self) {
+        self.stream.send(&Message::Ok(Payload::None));
+    }
+
+    fn answer_msg(&mut self, payload: Payload) {
+        self.stream.send(&Message::Ok(payload));
+    }
+
+    pub fn answer_err(&mut self, msg: &str) {
+        self.stream.send(&Message::Err(msg.to_string()));
+    }
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    // how the server sets up its tables
+    pub bots: usize,      // number of seats that are taken by bots at every new table
+    pub replays: PathBuf, // directory where the replays of finished games are saved
+    pub seed: Option<u64>, // for the deals at tables that don't have their own seed
+    pub tournament: Option<Arc<Tournament>>, // all tables play the same deals
+    pub spectator_delay: Duration, // for spectators with the delayed view
+    pub limits: Limits,   // how long the players may take for their moves
+    pub snapshots: PathBuf, // directory where the state of every game is saved
+    pub accounts: Option<Arc<Accounts>>, // players have to log in if there are accounts
+    pub stats: Arc<Stats>, // statistics and ratings of the players
+    pub rules: RuleSet,   // the variant that is played at every new table
+}
+
+pub struct Lobby {
+    tables: Mutex<BTreeMap<String, Table>>,
+    settings: Settings,
+}
+
+impl Lobby {
+    pub fn new(settings: Settings) -> Lobby {
+        Lobby {
+            tables: Mutex::new(BTreeMap::new()),
+            settings,
+        }
+    }
+
+    pub fn authenticate(&self, login: Login) -> Result<String, String> {
+        // returns the username of a client that has sent its hello
+        match (&self.settings.accounts, login) {
+            (None, Login::Guest(username)) => {
+                // the name is sent to the other players, e.g. in lists separated by ','
+                accounts::check_username(&username).map(|_| username)
+            }
+            (None, _) => Err("This server has no accounts, just send your username".to_string()),
+            (Some(_), Login::Guest(_)) => Err(
+                "Send 'login <username> <password>' or 'register <username> <password>'"
+                    .to_string(),
+            ),
+            (Some(accounts), Login::Password(username, password)) => {
+                accounts.login(&username, &password).map(|_| username)
+            }
+            (Some(accounts), Login::Register(username, password)) => {
+                accounts.register(&username, &password).map(|_| username)
+            }
+        }
+    }
+
+    pub fn game_of(&self, visitor: &mut Visitor) -> Option<(Arc<TichuConnection>, usize)> {
+        // returns the game and seat of the visitor if their table has started
+        let tables = self.lock_tables();
+        let (name, seat) = visitor.seat_at(&tables)?;
+        tables[&name].game.clone().map(|game| (game, seat))
+    }
+
+    pub fn handle_message(&self, visitor: &mut Visitor, msg: &str) {
+        let mut parts = msg.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("tables"), None, None) => {
+                let list = self.list_tables();
+                visitor.answer_msg(list);
+            }
+            (Some("create"), Some(name), None) => self.create(visitor, name, None),
+            (Some("create"), Some(name), Some(seed)) => match seed.parse() {
+                Ok(seed) => self.create(visitor, name, Some(seed)),
+                Err(_) => visitor.answer_err("The seed must be a positive number"),
+            },
+            (Some("join"), Some(name), Some(seat)) => match seat.parse() {
+                Ok(seat) => self.join(visitor, name, seat),
+                Err(_) => visitor.answer_err("The seat must be a number between 0 and 3"),
+            },
+            (Some("leave"), None, None) => {
+                self.leave(visitor);
+                visitor.answer_ok();
+            }
+            (Some("sit"), Some(seat), None) => match seat.parse() {
+                Ok(seat) => self.sit(visitor, seat),
+                Err(_) => visitor.answer_err("The seat must be a number between 0 and 3"),
+            },
+            (Some("swap"), Some(seat), None) => match seat.parse() {
+                Ok(seat) => self.swap(visitor, seat),
+                Err(_) => visitor.answer_err("The seat must be a number between 0 and 3"),
+            },
+            (Some("teamname"), Some(name), None) => self.name_team(visitor, name),
+            (Some("ready"), None, None) => self.ready(visitor),
+            (Some("start"), None, None) => self.start(visitor),
+            (Some("reconnect"), Some(token), None) => self.reconnect(visitor, token),
+            (Some("addbot"), Some(name), Some(seat)) => match seat.parse() {
+                Ok(seat) => self.add_bot(visitor, name, seat),
+                Err(_) => visitor.answer_err("The seat must be a number between 0 and 3"),
+            },
+            (Some("watch"), Some(name), view) => self.watch(visitor, name, view),
+            (Some("stats"), name, None) => {
+                let username = name.unwrap_or(&visitor.username).to_string();
+                match self.settings.stats.get(&username) {
+                    Some(stats) => visitor.answer_msg(Payload::stats(&username, &stats)),
+                    None => visitor.answer_err("This player hasn't finished a game yet"),
+                }
+            }
+            (Some("leaderboard"), None, None) => {
+                let ranking = self.settings.stats.leaderboard(LEADERBOARD_SIZE);
+                visitor.answer_msg(Payload::leaderboard(&ranking));
+            }
+            _ if self.is_watching(visitor) => visitor.answer_err("Spectators can't play"),
+            _ => {
+                warn!(
+                    "received invalid message from {}: {}",
+                    visitor.username, msg
+                );
+                visitor.answer_err("invalid command");
+            }
+        }
+    }
+
+    pub fn chat(&self, visitor: &mut Visitor, chat: &Chat) {
+        // chat messages and emotes go to the visitor's table, whether the game has started or not
+        let tables = self.lock_tables();
+        let (name, seat) = match visitor.seat_at(&tables) {
+            Some(seat) => seat,
+            None => {
+                visitor.answer_err("You don't sit at a table");
+                return;
+            }
+        };
+        if !visitor.chat_limit.allow(Instant::now()) {
+            visitor.answer_err("You are sending messages too quickly");
+            return;
+        }
+        visitor.answer_ok();
+        let table = &tables[&name];
+        match table.game.clone() {
+            Some(game) => {
+                drop(tables);
+                game.chat(seat, chat);
+            }
+            None => {
+                let outbox = table.chat_messages(seat, chat);
+                drop(tables);
+                deliver(outbox);
+            }
+        }
+    }
+
+    fn lock_tables(&self) -> MutexGuard<'_, BTreeMap<String, Table>> {
+        // get rid of tables whose game has ended before anyone looks at them
+        let mut tables = self.tables.lock().unwrap();
+        tables.retain(|_, table| match &table.game {
+            Some(game) => !game.is_closed(),
+            None => true,
+        });
+        tables
+    }
+
+    fn list_tables(&self) -> Payload {
+        let tables = self.lock_tables();
+        Payload::Tables(
+            tables
+                .iter()
+                .map(|(name, table)| TableData {
+                    name: name.clone(),
+                    seats: table.usernames(),
+                    playing: table.game.is_some(),
+                })
+                .collect(),
+        )
+    }
+
+    fn create(&self, visitor: &mut Visitor, name: &str, seed: Option<u64>) {
+        let mut tables = self.lock_tables();
+        if !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            visitor.answer_err("Table names may only contain letters, digits, '-' and '_'");
+        } else if tables.contains_key(name) {
+            visitor.answer_err("There already is a table with this name");
+        } else {
+            info!("{} created the table {}", visitor.username, name);
+            let mut table = Table::new(seed);
+            // bots take the last seats, so that the creator can sit down at seat 0
+            for seat in 4 - self.settings.bots..4 {
+                table.seat_bot(seat);
+            }
+            tables.insert(name.to_string(), table);
+            visitor.answer_ok();
+        }
+    }
+
+    fn join(&self, visitor: &mut Visitor, name: &str, seat: usize) {
+        if visitor.seat.is_some() {
+            visitor.answer_err("You already sit at a table");
+            return;
+        }
+        if self.is_watching(visitor) {
+            visitor.answer_err("Stop watching with 'leave' first");
+            return;
+        }
+        let mut tables = self.lock_tables();
+        let table = match tables.get_mut(name) {
+            Some(table) => table,
+            None => {
+                visitor.answer_err("There is no table with this name");
+                return;
+            }
+        };
+        if table.game.is_some() {
+            visitor.answer_err("The game at this table has already started");
+        } else if seat > 3 || table.seats[seat].is_some() {
+            visitor.answer_err("This seat is not available");
+        } else if table.usernames().contains(&Some(visitor.username.clone())) {
+            visitor.answer_err("Somebody with your username already sits at this table");
+        } else {
+            let token = new_token();
+            table.seats[seat] = Some(Seat {
+                username: visitor.username.clone(),
+                stream: visitor.stream.clone(),
+                token: token.clone(),
+                bot: None,
+                ready: false,
+            });
+            visitor.seat = Some((name.to_string(), token.clone()));
+            visitor.answer_msg(Payload::Text(token));
+            let outbox = table.seating_changed();
+            drop(tables);
+            deliver(outbox);
+        }
+    }
+
+    pub fn leave(&self, visitor: &mut Visitor) {
+        // free the visitor's seat, unless the game has already started. spectators stop watching
+        let mut tables = self.lock_tables();
+        let mut outbox = Vec::new();
+        if let Some((name, id)) = visitor.watching.take() {
+            if let Some(game) = tables.get(&name).and_then(|table| table.game.as_ref()) {
+                game.unwatch(id);
+            }
+        }
+        if let Some((name, seat)) = visitor.seat_at(&tables) {
+            visitor.seat = None;
+            let table = tables.get_mut(&name).unwrap();
+            if table.game.is_none() {
+                table.seats[seat] = None;
+                outbox = table.seating_changed();
+            }
+        }
+        drop(tables);
+        deliver(outbox);
+    }
+
+    fn waiting_table<'a>(
+        &self,
+        tables: &'a mut BTreeMap<String, Table>,
+        visitor: &mut Visitor,
+    ) -> Result<(&'a mut Table, usize), &'static str> {
+        // the visitor's table and seat, as long as the seating can be changed
+        let (name, seat) = visitor.seat_at(tables).ok_or("You don't sit at a table")?;
+        let table = tables.get_mut(&name).unwrap();
+        match table.game {
+            Some(_) => Err("The game at this table has already started"),
+            None => Ok((table, seat)),
+        }
+    }
+
+    fn sit(&self, visitor: &mut Visitor, seat: usize) {
+        // move to a free seat of the visitor's table
+        let mut tables = self.lock_tables();
+        let outbox = match self.waiting_table(&mut tables, visitor) {
+            Ok((table, from)) if seat < 4 && table.seats[seat].is_none() => {
+                table.seats[seat] = table.seats[from].take();
+                visitor.answer_ok();
+                table.seating_changed()
+            }
+            Ok(_) => {
+                visitor.answer_err("This seat is not available");
+                return;
+            }
+            Err(e) => {
+                visitor.answer_err(e);
+                return;
+            }
+        };
+        drop(tables);
+        deliver(outbox);
+    }
+
+    fn swap(&self, visitor: &mut Visitor, seat: usize) {
+        // bots swap right away, players have to ask for the swap with each other
+        let mut tables = self.lock_tables();
+        let (table, from) = match self.waiting_table(&mut tables, visitor) {
+            Ok(found) => found,
+            Err(e) => {
+                visitor.answer_err(e);
+                return;
+            }
+        };
+        let agreed = match table.seats.get(seat) {
+            Some(Some(other)) if seat != from => {
+                other.stream.is_bot() || table.swaps[seat] == Some(from)
+            }
+            _ => {
+                visitor.answer_err("There is nobody to swap with at this seat");
+                return;
+            }
+        };
+        visitor.answer_ok();
+        let outbox = if agreed {
+            table.seats.swap(from, seat);
+            table.seating_changed()
+        } else {
+            // the other player agrees by asking for the swap as well
+            table.swaps[from] = Some(seat);
+            table.push_to_seat(seat, "swap", Payload::Seat(from))
+        };
+        drop(tables);
+        deliver(outbox);
+    }
+
+    fn name_team(&self, visitor: &mut Visitor, name: &str) {
+        // either player of a team can give it a name until the seating changes
+        let mut tables = self.lock_tables();
+        let outbox = match self.waiting_table(&mut tables, visitor) {
+            Ok(_)
+                if name.chars().count() > MAX_TEAM_NAME
+                    || !name
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '-' || c == '_') =>
+            {
+                visitor.answer_err(&format!(
+                    "Team names may only contain up to {} letters, digits, '-' and '_'",
+                    MAX_TEAM_NAME
+                ));
+                return;
+            }
+            Ok((table, seat)) => {
+                table.team_names[seat % 2] = Some(name.to_string());
+                visitor.answer_ok();
+                table.push_to_seats("teams", Payload::Teams(table.team_names()))
+            }
+            Err(e) => {
+                visitor.answer_err(e);
+                return;
+            }
+        };
+        drop(tables);
+        deliver(outbox);
+    }
+
+    fn ready(&self, visitor: &mut Visitor) {
+        // agree with the seating, the game can start once all four players are ready
+        let mut tables = self.lock_tables();
+        let outbox = match self.waiting_table(&mut tables, visitor) {
+            Ok((table, seat)) => {
+                table.seats[seat].as_mut().unwrap().ready = true;
+                visitor.answer_ok();
+                table.push_to_seats("ready", Payload::Seat(seat))
+            }
+            Err(e) => {
+                visitor.answer_err(e);
+                return;
+            }
+        };
+        drop(tables);
+        deliver(outbox);
+    }
+
+    fn start(&self, visitor: &mut Visitor) {
+        let mut tables = self.lock_tables();
+        let name = match visitor.seat_at(&tables) {
+            Some((name, _)) => name,
+            None => {
+                visitor.answer_err("You don't sit at a table");
+                return;
+            }
+        };
+        let table = tables.get_mut(&name).unwrap();
+        if !table.is_full() {
+            visitor.answer_err("The game can only start once all four seats are taken");
+            return;
+        }
+        if !table.is_ready() {
+            visitor.answer_err("The game can only start once all four players are ready");
+            return;
+        }
+        visitor.answer_ok();
+        // without a seed for the table or the server, every game is different
+        let seed = table
+            .seed
+            .or(self.settings.seed)
+            .unwrap_or_else(|| thread_rng().gen());
+        let game = Arc::new(TichuConnection::new(
+            table.streams(),
+            table.saved_seats(),
+            &name,
+            &self.settings,
+            seed,
+            self.settings.tournament.as_ref().map(Tournament::enter),
+        ));
+        table.spawn_bots(&game);
+        game.start();
+        if self.settings.limits.enabled() {
+            timer::spawn(game.clone());
+        }
+        info!(
+            "the game at table {} has started with the seed {}",
+            name, seed
+        );
+        table.game = Some(game);
+    }
+
+    pub fn resume(&self, restored: Restored) -> Result<(), String> {
+        // set up the table of a snapshot, the players come back with their tokens
+        let mut tables = self.lock_tables();
+        let name = restored.table.clone();
+        if tables.contains_key(&name) {
+            return Err(format!("there already is a table called {}", name));
+        }
+        let mut table = Table::new(None);
+        for (i, seat) in restored.seats.iter().enumerate() {
+            if seat.bot {
+                table.seat_bot(i);
+            } else {
+                table.seats[i] = Some(Seat {
+                    username: seat.username.clone(),
+                    stream: Client::disconnected(),
+                    token: seat.token.clone(),
+                    bot: None,
+                    ready: true,
+                });
+            }
+            table.seats[i].as_mut().unwrap().token = seat.token.clone();
+        }
+        // the tournament goes on if the server was started with the same deals
+        let tournament = match (&self.settings.tournament, restored.rotated) {
+            (Some(tournament), Some(rotated)) => Some(Entry {
+                tournament: tournament.clone(),
+                rotated,
+            }),
+            _ => None,
+        };
+        let game = Arc::new(TichuConnection::restore(
+            table.streams(),
+            restored,
+            &self.settings,
+            tournament,
+        ));
+        table.spawn_bots(&game);
+        game.resume();
+        if self.settings.limits.enabled() {
+            timer::spawn(game.clone());
+        }
+        info!("resumed the game at table {}", name);
+        table.game = Some(game);
+        tables.insert(name, table);
+        Ok(())
+    }
+
+    fn reconnect(&self, visitor: &mut Visitor, token: &str) {
+        // take the seat at a running game that belongs to the token
+        if visitor.seat.is_some() {
+            visitor.answer_err("You already sit at a table");
+            return;
+        }
+        if self.is_watching(visitor) {
+            visitor.answer_err("Stop watching with 'leave' first");
+            return;
+        }
+        let mut tables = self.lock_tables();
+        let found = tables
+            .iter_mut()
+            .find_map(|(name, table)| table.seat_of(token).map(|seat| (name, table, seat)));
+        match found {
+            Some((name, table, seat)) => match &table.game {
+                // with accounts, nobody can take the seat of somebody else
+                Some(_)
+                    if self.settings.accounts.is_some()
+                        && table.seats[seat].as_ref().unwrap().username != visitor.username =>
+                {
+                    visitor.answer_err("This token belongs to another player")
+                }
+                Some(game) => {
+                    visitor.username = table.seats[seat].as_ref().unwrap().username.clone();
+                    visitor.seat = Some((name.clone(), token.to_string()));
+                    // the seat knows where the player connects from, see watch()
+                    table.seats[seat].as_mut().unwrap().stream = visitor.stream.clone();
+                    // the game answers and sends the current state
+                    game.reconnect(seat, visitor.stream.clone());
+                }
+                None => visitor.answer_err("The game at this table hasn't started yet"),
+            },
+            None => visitor.answer_err("This token is not valid"),
+        }
+    }
+
+    fn add_bot(&self, visitor: &mut Visitor, name: &str, seat: usize) {
+        let mut tables = self.lock_tables();
+        let outbox = match tables.get_mut(name) {
+            Some(table) if table.game.is_none() && seat < 4 && table.seats[seat].is_none() => {
+                info!(
+                    "{} added a bot to seat {} of table {}",
+                    visitor.username, seat, name
+                );
+                table.seat_bot(seat);
+                visitor.answer_ok();
+                table.seating_changed()
+            }
+            Some(_) => {
+                visitor.answer_err("This seat is not available");
+                return;
+            }
+            None => {
+                visitor.answer_err("There is no table with this name");
+                return;
+            }
+        };
+        drop(tables);
+        deliver(outbox);
+    }
+
+    fn watch(&self, visitor: &mut Visitor, name: &str, view: Option<&str>) {
+        // watch a running game, "hands" shows the cards of all players, "delayed" does
+        // too but everything arrives late, so that spectators can't tell the players
+        let delay = match view {
+            None | Some("hands") => None,
+            Some("delayed") => Some(self.settings.spectator_delay),
+            Some(_) => {
+                visitor.answer_err("The view must be 'hands' or 'delayed'");
+                return;
+            }
+        };
+        // guests can pick any name, only players with an account see all cards right away
+        if view == Some("hands") && self.settings.accounts.is_none() {
+            visitor.answer_err(
+                "Without accounts, the cards of all players are only shown with the view 'delayed'",
+            );
+            return;
+        }
+        if visitor.seat.is_some() || self.is_watching(visitor) {
+            visitor.answer_err("You already sit at a table or watch one");
+            return;
+        }
+        let tables = self.lock_tables();
+        let table = match tables.get(name) {
+            Some(table) => table,
+            None => {
+                visitor.answer_err("There is no table with this name");
+                return;
+            }
+        };
+        // players could see the cards of the others from a second connection. accounts are
+        // verified, guests are recognised by their address
+        let peer = visitor.stream.peer();
+        let guests = self.settings.accounts.is_none();
+        if table.seats.iter().flatten().any(|seat| {
+            seat.username == visitor.username
+                || (guests && peer.is_some() && seat.stream.peer() == peer)
+        }) {
+            visitor.answer_err("You can't watch a table you are playing at");
+            return;
+        }
+        match &table.game {
+            Some(game) => {
+                visitor.answer_ok();
+                let stream = match delay {
+                    Some(delay) => Client::delayed(visitor.stream.clone(), delay),
+                    None => visitor.stream.clone(),
+                };
+                let id = game.watch(stream, view.is_some());
+                visitor.watching = Some((name.to_string(), id));
+                info!("{} watches the game at table {}", visitor.username, name);
+            }
+            None => visitor.answer_err("The game at this table hasn't started yet"),
+        }
+    }
+
+    fn is_watching(&self, visitor: &mut Visitor) -> bool {
+        // a spectator is back in the lobby once the table is closed
+        let tables = self.lock_tables();
+        if let Some((name, _)) = &visitor.watching {
+            if !tables.contains_key(name) {
+                visitor.watching = None;
+            }
+        }
+        visitor.watching.is_some()
+    }
+}
+
+fn new_token() -> String {
+    thread_rng().sample_iter(&Alphanumeric).take(16).collect()
+}
@@ -1,15 +1,37 @@
+mod accounts;
+mod bot;
+mod chat;
 mod combinations;
 mod deck;
+mod lobby;
 mod player;
+mod protocol;
+mod replay;
+mod rules;
+mod snapshot;
+mod stats;
 mod tichugame;
 mod tichuserver;
+mod timer;
+mod tournament;
+mod transport;
 
 #[macro_use]
 extern crate clap;
-use clap::App;
-use log::error;
+use accounts::Accounts;
+use clap::{App, ArgMatches};
+use lobby::Settings;
+use log::{error, info};
+use rules::RuleSet;
 use simplelog::{Config, LevelFilter, TermLogger, TerminalMode};
+use snapshot::Restored;
+use stats::Stats;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tichuserver::TichuServer;
+use timer::Limits;
+use tournament::Tournament;
 
 fn main() {
     // set up logger
@@ -17,18 +39,145 @@ fn main() {
     // set up argument parser
     let options = load_yaml!("cli.yml");
     let args = App::from_yaml(options).get_matches();
+    if let Some(replay) = args.subcommand_matches("replay") {
+        let file = Path::new(replay.value_of("FILE").unwrap());
+        if let Err(e) = replay::show(file, replay.is_present("step")) {
+            error!("{}", e);
+        }
+        return;
+    }
+    if let Some(deal) = args.subcommand_matches("deal") {
+        match deal.value_of("SEED").unwrap().parse() {
+            Ok(seed) => replay::show_deal(seed),
+            Err(_) => error!("the seed must be a positive number"),
+        }
+        return;
+    }
     let ip = args.value_of("ip_address").unwrap_or("127.0.0.1");
     let port = args.value_of("port").unwrap_or("1001");
+    let websocket_port = args.value_of("websocket_port");
+    let bots = match args.value_of("bots").unwrap_or("0").parse::<usize>() {
+        Ok(n) if n < 4 => n,
+        _ => {
+            error!("the number of bots must be between 0 and 3");
+            return;
+        }
+    };
 
-    let server = TichuServer::accept(ip, port);
+    let replays = PathBuf::from(args.value_of("replays").unwrap_or("replays"));
+    // with a deal file, every table plays the same deals
+    let tournament = match args.value_of("deals") {
+        Some(deals) => match Tournament::load(Path::new(deals), replays.join("tournament.txt")) {
+            Ok(tournament) => Some(Arc::new(tournament)),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    // without an account file, everyone can play under any name
+    let accounts = match args.value_of("accounts") {
+        Some(path) => match Accounts::load(Path::new(path)) {
+            Ok(accounts) => Some(Arc::new(accounts)),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let stats = match Stats::load(Path::new(args.value_of("stats").unwrap_or("stats.json"))) {
+        Ok(stats) => Arc::new(stats),
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let overrides: Vec<&str> = args.values_of("rule").into_iter().flatten().collect();
+    let rules = match RuleSet::load(args.value_of("rules").map(Path::new), &overrides) {
+        Ok(rules) => rules,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    info!("the rules are {}", rules);
+    let settings = Settings {
+        bots,
+        replays,
+        seed: match args.value_of("seed").map(|s| s.parse::<u64>()) {
+            None => None,
+            Some(Ok(seed)) => Some(seed),
+            Some(Err(_)) => {
+                error!("the seed must be a positive number");
+                return;
+            }
+        },
+        tournament,
+        snapshots: PathBuf::from(args.value_of("snapshots").unwrap_or("snapshots")),
+        spectator_delay: match seconds(args.value_of("spectator_delay").unwrap_or("60")) {
+            Ok(delay) => delay,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        },
+        limits: match limits(&args) {
+            Ok(limits) => limits,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        },
+        accounts,
+        stats,
+        rules,
+    };
+
+    // the tables of the snapshots are set up before the server accepts anyone
+    let mut restored = Vec::new();
+    for path in args.values_of("resume").into_iter().flatten() {
+        match Restored::load(Path::new(path)) {
+            Ok(snapshot) => restored.push(snapshot),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+
+    let server = TichuServer::bind(ip, port, websocket_port, settings);
     match server {
         Ok(mut server) => {
+            for snapshot in restored {
+                if let Err(e) = server.resume(snapshot) {
+                    error!("{}", e);
+                    return;
+                }
+            }
             server.main();
             server.stop();
         }
         Err(e) => {
             error!("{}", e);
-            return;
         }
     }
 }
+
+fn limits(args: &ArgMatches) -> Result<Limits, String> {
+    // without a turn or exchange time, the players may take as long as they want
+    let optional = |name| args.value_of(name).map(seconds).transpose();
+    Ok(Limits {
+        turn: optional("turn_time")?,
+        exchange: optional("exchange_time")?,
+        warning: seconds(args.value_of("timer_warning").unwrap_or("10"))?,
+    })
+}
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    match value.parse() {
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(_) => Err(format!("{} is not a number of seconds", value)),
+    }
+}
@@ -1,9 +1,12 @@
-use crate::combinations::Trick;
-use crate::deck::Card;
+use crate::combinations::{legal_tricks, tricks_with_kind, Trick};
+use crate::deck::{Card, Kind, RegularKind};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+#[derive(Serialize, Deserialize)]
 pub struct Player {
     hand: HashMap<usize, Card>,
+    next_id: usize, // identifyer for the next card that gets added to the hand
     pub username: String,
 }
 
@@ -11,30 +14,67 @@ impl Player {
     pub fn new(username: String) -> Player {
         Player {
             hand: HashMap::new(),
-            username: username,
+            next_id: 0,
+            username,
         }
     }
 
     pub fn take_new_hand(&mut self, hand: Vec<Card>) {
+        // throw away whatever is left from the last round and start counting at 0 again
+        self.hand.clear();
+        self.next_id = 0;
+        self.add_cards(hand);
+    }
+
+    pub fn add_cards(&mut self, cards: Vec<Card>) {
         // store each card in a hash map with an index works as an identifyer
-        let mut id = 0;
-        for c in hand {
-            self.hand.insert(id, c);
-            id += 1;
+        // new cards get the identifyers following the ones that were handed out before
+        for c in cards {
+            self.hand.insert(self.next_id, c);
+            self.next_id += 1;
         }
     }
 
-    pub fn play(&mut self, trick_to_top: Option<&Trick>, cards: &Vec<usize>) -> Result<Trick, PlayerError> {
-        // build the own trick
-        let mut own_trick = Trick::new();
-        for i in cards {
-            let card = self.hand.get(i);
-            match card {
-                Some(c) => own_trick.push(*c),
+    pub fn get_hand(&self) -> Vec<(usize, &Card)> {
+        // all cards together with their identifyers, sorted by the identifyers
+        let mut hand: Vec<(usize, &Card)> = self.hand.iter().map(|(i, c)| (*i, c)).collect();
+        hand.sort_by_key(|(i, _)| *i);
+        hand
+    }
+
+    pub fn give_cards(&mut self, cards: &[usize]) -> Result<Vec<Card>, PlayerError> {
+        // remove the cards from the hand and return them, e.g. to pass them to other players
+        let mut given = Vec::new();
+        for (n, i) in cards.iter().enumerate() {
+            if cards[..n].contains(i) {
+                return Err(PlayerError::InvalidCard);
+            }
+            match self.hand.get(i) {
+                Some(c) => given.push(*c),
                 None => return Err(PlayerError::InvalidCard),
             }
         }
+        self.remove_cards(cards);
+        Ok(given)
+    }
+
+    pub fn play(
+        &mut self,
+        trick_to_top: Option<&Trick>,
+        cards: &[usize],
+        wish: Option<RegularKind>,
+    ) -> Result<Trick, PlayerError> {
+        let mut own_trick = self.build_trick(cards)?;
+        // if there is an open wish, the player must fulfil it if they can
+        if let Some(kind) = wish {
+            if !own_trick.contains_kind(Kind::Regular(kind))
+                && self.can_fulfil_wish(trick_to_top, kind)
+            {
+                return Err(PlayerError::MustFulfilWish);
+            }
+        }
         // if the player is first, trick is None, else the own stage must top the trick
+        own_trick.set_phoenix_rank(trick_to_top);
         match trick_to_top {
             None => {
                 if own_trick.is_valid() {
@@ -44,33 +84,137 @@ impl Player {
                     Err(PlayerError::NotValid)
                 }
             }
-            Some(trick) => {
-                match own_trick.tops(trick) {
-                    Some(true) => {
-                        self.remove_cards(cards);
-                        Ok(own_trick)
-                    }
-                    Some(false) => Err(PlayerError::TooLow),
-                    None => Err(PlayerError::Incompatible),
+            Some(trick) => match own_trick.tops(trick) {
+                Some(true) => {
+                    self.remove_cards(cards);
+                    Ok(own_trick)
                 }
+                Some(false) => Err(PlayerError::TooLow),
+                None => Err(PlayerError::Incompatible),
+            },
+        }
+    }
+
+    pub fn play_bomb(
+        &mut self,
+        trick_to_top: &Trick,
+        cards: &[usize],
+    ) -> Result<Trick, PlayerError> {
+        // bombs may be played out of turn, but only on top of another trick
+        let own_trick = self.build_trick(cards)?;
+        if !own_trick.is_bomb() {
+            return Err(PlayerError::NotABomb);
+        }
+        match own_trick.tops(trick_to_top) {
+            Some(true) => {
+                self.remove_cards(cards);
+                Ok(own_trick)
+            }
+            Some(false) => Err(PlayerError::TooLow),
+            None => Err(PlayerError::Incompatible),
+        }
+    }
+
+    fn build_trick(&self, cards: &[usize]) -> Result<Trick, PlayerError> {
+        let mut own_cards = Vec::new();
+        for (n, i) in cards.iter().enumerate() {
+            // every card can only be used once
+            if cards[..n].contains(i) {
+                return Err(PlayerError::InvalidCard);
+            }
+            let card = self.hand.get(i);
+            match card {
+                Some(c) => own_cards.push(*c),
+                None => return Err(PlayerError::InvalidCard),
             }
         }
+        Ok(Trick::from_cards(own_cards))
     }
 
-    fn remove_cards(&mut self, cards: &Vec<usize>) {
+    fn remove_cards(&mut self, cards: &[usize]) {
         for i in cards {
             self.hand.remove(i);
         }
     }
 
     pub fn has_cards(&self) -> bool {
-        self.hand.len() > 0
+        !self.hand.is_empty()
+    }
+
+    pub fn selects(&self, cards: &[usize], card: &Card) -> bool {
+        // check if card is among the cards with the given identifyers
+        cards.iter().any(|i| self.hand.get(i) == Some(card))
+    }
+
+    pub fn legal_moves(
+        &self,
+        trick_to_top: Option<&Trick>,
+        wish: Option<RegularKind>,
+    ) -> Vec<(Vec<usize>, Trick)> {
+        // all tricks that the player could play, with the identifyers of their cards
+        let hand = self.get_hand();
+        let cards: Vec<Card> = hand.iter().map(|(_, c)| **c).collect();
+        let mut moves: Vec<(Vec<usize>, Trick)> = legal_tricks(&cards, trick_to_top)
+            .into_iter()
+            .map(|(positions, trick)| (positions.iter().map(|n| hand[*n].0).collect(), trick))
+            .collect();
+        // if the wish can be fulfilled, it must be
+        if let Some(kind) = wish {
+            if self.can_fulfil_wish(trick_to_top, kind) {
+                moves.retain(|(_, trick)| trick.contains_kind(Kind::Regular(kind)));
+            }
+        }
+        moves
+    }
+
+    pub fn can_fulfil_wish(&self, trick_to_top: Option<&Trick>, wish: RegularKind) -> bool {
+        // check if the hand contains a card of the wished kind that can be played
+        // in a legal combination on top of trick_to_top
+        let cards: Vec<Card> = self.hand.values().cloned().collect();
+        match trick_to_top {
+            // every card can be led as a singlet
+            None => cards.iter().any(|c| c.kind == Kind::Regular(wish)),
+            Some(other) => tricks_with_kind(&cards, wish)
+                .iter()
+                .any(|trick| trick.tops(other) == Some(true)),
+        }
     }
 }
 
+#[derive(Debug)]
 pub enum PlayerError {
     InvalidCard,
     NotValid,
     TooLow,
     Incompatible,
+    MustFulfilWish,
+    NotABomb,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::Color;
+
+    #[test]
+    fn test_repeated_cards() {
+        let mut player = Player::new("alice".to_string());
+        player.take_new_hand(vec![
+            Card::regular(RegularKind::Three, Color::Red),
+            Card::regular(RegularKind::Three, Color::Blue),
+            Card::regular(RegularKind::Four, Color::Red),
+        ]);
+        let trick = Trick::from_cards(vec![Card::regular(RegularKind::Ace, Color::Green)]);
+        // a single three is neither a bomb nor a pair
+        assert!(matches!(
+            player.play_bomb(&trick, &[0, 0, 0, 0]),
+            Err(PlayerError::InvalidCard)
+        ));
+        assert!(matches!(
+            player.play(None, &[0, 0], None),
+            Err(PlayerError::InvalidCard)
+        ));
+        assert!(player.play(None, &[0, 1], None).is_ok());
+        assert_eq!(player.get_hand().len(), 1);
+    }
 }
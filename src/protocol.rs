@@ -0,0 +1,763 @@
+use crate::combinations::Trick;
+use crate::deck::{Card, Kind};
+use crate::rules::RuleSet;
+use crate::stats::PlayerStats;
+use crate::tichugame::TichuGame;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::iter;
+
+// version of the json protocol, clients have to send it in their hello
+pub const JSON_VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Protocol {
+    Line, // "ok:", "err:msg", "push:topic:msg"
+    Json, // one json object per line
+}
+
+#[derive(Clone)]
+pub enum Message {
+    Ok(Payload),
+    Err(String),
+    Push(&'static str, Payload),
+}
+
+impl Message {
+    pub fn to_line(&self) -> String {
+        match self {
+            Message::Ok(payload) => format!("ok:{}", payload.to_line()),
+            Message::Err(msg) => format!("err:{}", msg),
+            Message::Push(topic, payload) => format!("push:{}:{}", topic, payload.to_line()),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // the data is null if there is nothing to send, otherwise its type tells what it is
+        let data = |payload: &Payload| match payload {
+            Payload::None => Value::Null,
+            _ => json!(payload),
+        };
+        let value = match self {
+            Message::Ok(payload) => json!({"type": "response", "ok": true, "data": data(payload)}),
+            Message::Err(msg) => json!({"type": "response", "ok": false, "error": msg}),
+            Message::Push(topic, payload) => {
+                json!({"type": "event", "topic": topic, "data": data(payload)})
+            }
+        };
+        value.to_string()
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum Payload {
+    None,
+    Text(String),
+    Seat(usize),
+    Seconds(u64),
+    Cards(Vec<CardData>),      // the line protocol leaves out the identifyers
+    Hand(Vec<CardData>),       // the whole hand, with the identifyers in both protocols
+    Hands(Vec<Vec<CardData>>), // the cards of every player, for spectators
+    Trick(TrickData),
+    Scores(ScoreData),
+    Seats(Vec<Option<String>>),
+    Teams(Vec<String>), // names of the teams 0,2 and 1,3
+    Tables(Vec<TableData>),
+    Vote(VoteData),
+    Moves(Vec<MoveData>),
+    Chat(ChatData),
+    Stats(StatsData),
+    Leaderboard(Vec<RatingData>),
+    Rules(RuleSet),
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct CardData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>, // only set for cards that the player holds
+    pub kind: String,
+    pub color: Option<String>,
+    #[serde(skip)]
+    name: String, // "Red Five", used by the line protocol
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct TrickData {
+    pub combination: Option<String>,
+    pub cards: Vec<CardData>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct ScoreData {
+    pub total: [i16; 2],
+    pub rounds: Vec<[i16; 2]>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct TableData {
+    pub name: String,
+    pub seats: Vec<Option<String>>,
+    pub playing: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct MoveData {
+    pub cards: Vec<usize>, // identifyers of the cards in the player's hand
+    pub combination: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct VoteData {
+    pub seat: usize,
+    pub rematch: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct ChatData {
+    pub seat: usize,
+    pub to: String, // "table" or "partner"
+    pub text: String,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct StatsData {
+    pub username: String,
+    pub games: u32,
+    pub wins: u32,
+    pub tichus: u32,
+    pub tichus_made: u32,
+    pub grand_tichus: u32,
+    pub grand_tichus_made: u32,
+    pub double_victories: u32,
+    pub average_points: f64, // of the player's team per round
+    pub rating: i32,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct RatingData {
+    pub username: String,
+    pub rating: i32,
+    pub games: u32,
+}
+
+impl Payload {
+    pub fn hand(hand: &[(usize, &Card)]) -> Payload {
+        Payload::Hand(card_data(hand))
+    }
+
+    pub fn cards_with_ids(cards: &[(usize, &Card)]) -> Payload {
+        // cards that were just added to the hand, json clients need their identifyers
+        Payload::Cards(card_data(cards))
+    }
+
+    pub fn hands(hands: &[&[Card]]) -> Payload {
+        Payload::Hands(
+            hands
+                .iter()
+                .map(|hand| hand.iter().map(|c| CardData::new(None, c)).collect())
+                .collect(),
+        )
+    }
+
+    pub fn trick(trick: &Trick) -> Payload {
+        Payload::Trick(TrickData {
+            combination: trick.combination.map(|c| format!("{:?}", c)),
+            cards: trick.cards.iter().map(|c| CardData::new(None, c)).collect(),
+        })
+    }
+
+    pub fn moves(moves: &[(Vec<usize>, Trick)]) -> Payload {
+        Payload::Moves(
+            moves
+                .iter()
+                .map(|(cards, trick)| MoveData {
+                    cards: cards.clone(),
+                    combination: trick.combination.map(|c| format!("{:?}", c)),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn scores(game: &TichuGame) -> Payload {
+        // the first entry of the scores is the empty score at the beginning
+        let (team1, team2) = game.get_total_score();
+        Payload::Scores(ScoreData {
+            total: [team1, team2],
+            rounds: game.get_scores()[1..]
+                .iter()
+                .map(|points| [points[0], points[1]])
+                .collect(),
+        })
+    }
+
+    pub fn stats(username: &str, stats: &PlayerStats) -> Payload {
+        Payload::Stats(StatsData {
+            username: username.to_string(),
+            games: stats.games,
+            wins: stats.wins,
+            tichus: stats.tichus,
+            tichus_made: stats.tichus_made,
+            grand_tichus: stats.grand_tichus,
+            grand_tichus_made: stats.grand_tichus_made,
+            double_victories: stats.double_victories,
+            average_points: (stats.average_points() * 10.0).round() / 10.0,
+            rating: stats.rating.round() as i32,
+        })
+    }
+
+    pub fn leaderboard(ranking: &[(String, PlayerStats)]) -> Payload {
+        Payload::Leaderboard(
+            ranking
+                .iter()
+                .map(|(username, stats)| RatingData {
+                    username: username.clone(),
+                    rating: stats.rating.round() as i32,
+                    games: stats.games,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn text(text: &str) -> Payload {
+        Payload::Text(text.to_string())
+    }
+
+    pub fn to_line(&self) -> String {
+        // the format of the line protocol, e.g. "Red Five,Dragon," for cards
+        match self {
+            Payload::None => String::new(),
+            Payload::Text(text) => text.clone(),
+            Payload::Seat(seat) => seat.to_string(),
+            Payload::Seconds(seconds) => seconds.to_string(),
+            Payload::Cards(cards) => format_cards(cards, false),
+            Payload::Hand(cards) => format_cards(cards, true),
+            Payload::Hands(hands) => {
+                // the cards of the players 0 to 3, e.g. "Red Five,Dragon,;One,;..."
+                let hands: Vec<String> = hands.iter().map(|h| format_cards(h, false)).collect();
+                hands.join(";")
+            }
+            Payload::Trick(trick) => format_cards(&trick.cards, false),
+            Payload::Scores(scores) => {
+                // the final score followed by the points of every round, e.g. "1020,480;120,-20;..."
+                let mut str = format!("{},{}", scores.total[0], scores.total[1]);
+                for points in &scores.rounds {
+                    str += &format!(";{},{}", points[0], points[1]);
+                }
+                str
+            }
+            Payload::Seats(seats) => format_seats(seats),
+            Payload::Teams(teams) => teams.join(";"),
+            Payload::Tables(tables) => {
+                // something like "table1 alice,-,bob,- waiting;table2 a,b,c,d playing"
+                let entries: Vec<String> = tables
+                    .iter()
+                    .map(|t| {
+                        let status = if t.playing { "playing" } else { "waiting" };
+                        format!("{} {} {}", t.name, format_seats(&t.seats), status)
+                    })
+                    .collect();
+                entries.join(";")
+            }
+            Payload::Vote(vote) => {
+                format!("{},{}", vote.seat, if vote.rematch { "yes" } else { "no" })
+            }
+            Payload::Moves(moves) => {
+                // the identifyers of each move, e.g. "0;0 3;1 2 4 5 6"
+                let moves: Vec<String> = moves.iter().map(|m| join_ids(&m.cards)).collect();
+                moves.join(";")
+            }
+            // e.g. "2,partner,go for it", the text comes last because it may contain commas
+            Payload::Chat(chat) => format!("{},{},{}", chat.seat, chat.to, chat.text),
+            Payload::Stats(stats) => {
+                // e.g. "alice,12,7,3/5,0/1,2,48.5,1532", tichus and grand tichus as made/called
+                format!(
+                    "{},{},{},{}/{},{}/{},{},{:.1},{}",
+                    stats.username,
+                    stats.games,
+                    stats.wins,
+                    stats.tichus_made,
+                    stats.tichus,
+                    stats.grand_tichus_made,
+                    stats.grand_tichus,
+                    stats.double_victories,
+                    stats.average_points,
+                    stats.rating
+                )
+            }
+            Payload::Rules(rules) => rules.to_string(),
+            Payload::Leaderboard(ranking) => {
+                // rating and number of games of each player, e.g. "alice 1532 12;bob 1490 3"
+                let entries: Vec<String> = ranking
+                    .iter()
+                    .map(|r| format!("{} {} {}", r.username, r.rating, r.games))
+                    .collect();
+                entries.join(";")
+            }
+        }
+    }
+}
+
+impl CardData {
+    fn new(id: Option<usize>, card: &Card) -> CardData {
+        let kind = match card.kind {
+            Kind::Regular(k) => k.to_string(),
+            Kind::Special(k) => k.to_string(),
+        };
+        CardData {
+            id,
+            kind,
+            color: card.color.map(|c| c.to_string()),
+            name: card.to_string(),
+        }
+    }
+}
+
+fn card_data(cards: &[(usize, &Card)]) -> Vec<CardData> {
+    cards
+        .iter()
+        .map(|(i, c)| CardData::new(Some(*i), c))
+        .collect()
+}
+
+fn format_cards(cards: &[CardData], ids: bool) -> String {
+    // every card is followed by a comma, cards of the hand are preceded by their identifyer
+    let mut str = String::new();
+    for card in cards {
+        if let (true, Some(id)) = (ids, card.id) {
+            str += &format!("{} ", id);
+        }
+        str += &format!("{},", card.name);
+    }
+    str
+}
+
+fn format_seats(seats: &[Option<String>]) -> String {
+    // usernames of the seated players, "-" for free seats
+    let names: Vec<&str> = seats
+        .iter()
+        .map(|s| match s {
+            Some(name) => name.as_str(),
+            None => "-",
+        })
+        .collect();
+    names.join(",")
+}
+
+#[derive(Deserialize)]
+struct Hello {
+    protocol: String,
+    version: u32,
+    username: String,
+    password: Option<String>,
+    #[serde(default)]
+    register: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Login {
+    // who a client claims to be
+    Guest(String),            // just a username, on servers without accounts
+    Password(String, String), // username and password of an account
+    Register(String, String), // creates the account first
+}
+
+pub fn handshake(line: &str) -> Result<(Protocol, Login), String> {
+    // the first line of a client is either its username, "login <username> <password>" or
+    // "register <username> <password>" (line protocol) or a json hello like
+    // {"protocol": "json", "version": 1, "username": "alice", "password": "secret"}
+    let line = line.trim();
+    if !line.starts_with('{') {
+        let mut parts = line.split_whitespace();
+        let login = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("login"), Some(username), Some(password), None) => {
+                Login::Password(username.to_string(), password.to_string())
+            }
+            (Some("register"), Some(username), Some(password), None) => {
+                Login::Register(username.to_string(), password.to_string())
+            }
+            _ => Login::Guest(line.to_string()),
+        };
+        return Ok((Protocol::Line, login));
+    }
+    match serde_json::from_str::<Hello>(line) {
+        Ok(hello) if hello.protocol == "json" && hello.version == JSON_VERSION => {
+            let login = match (hello.password, hello.register) {
+                (None, _) => Login::Guest(hello.username),
+                (Some(password), false) => Login::Password(hello.username, password),
+                (Some(password), true) => Login::Register(hello.username, password),
+            };
+            Ok((Protocol::Json, login))
+        }
+        Ok(hello) => Err(format!(
+            "unsupported protocol {} version {}, the server speaks json version {}",
+            hello.protocol, hello.version, JSON_VERSION
+        )),
+        Err(e) => Err(format!("invalid hello: {}", e)),
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Tables,
+    Create {
+        name: String,
+        seed: Option<u64>,
+    },
+    Join {
+        name: String,
+        seat: usize,
+    },
+    Leave,
+    Sit {
+        seat: usize,
+    },
+    Swap {
+        seat: usize,
+    },
+    TeamName {
+        name: String,
+    },
+    Ready,
+    Start,
+    Reconnect {
+        token: String,
+    },
+    Watch {
+        name: String,
+        view: Option<String>,
+    },
+    TakeCards,
+    Hints,
+    GrandTichu,
+    Tichu,
+    Exchange {
+        cards: Vec<usize>,
+    },
+    Play {
+        cards: Vec<usize>,
+        wish: Option<String>,
+    },
+    Pass,
+    GiveDragon {
+        player: usize,
+    },
+    Rematch {
+        vote: bool,
+    },
+    Chat {
+        to: String,
+        text: String,
+    },
+    Emote {
+        to: String,
+        emote: String,
+    },
+    Stats {
+        username: Option<String>,
+    },
+    Leaderboard,
+}
+
+impl Request {
+    fn check_words(&self) -> Result<(), String> {
+        // every string becomes one word of the line command, so it can't be empty or contain
+        // whitespace. only the text of a chat message is the rest of the line
+        let words: Vec<&String> = match self {
+            Request::Create { name, .. } | Request::Join { name, .. } => vec![name],
+            Request::TeamName { name } => vec![name],
+            Request::Reconnect { token } => vec![token],
+            Request::Watch { name, view } => iter::once(name).chain(view).collect(),
+            Request::Play { wish, .. } => wish.iter().collect(),
+            Request::Chat { to, .. } => vec![to],
+            Request::Emote { to, emote } => vec![to, emote],
+            Request::Stats { username } => username.iter().collect(),
+            _ => Vec::new(),
+        };
+        match words
+            .iter()
+            .find(|w| w.is_empty() || w.contains(char::is_whitespace))
+        {
+            Some(word) => Err(format!("invalid request: '{}' must be a single word", word)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        // the command as it would have been sent with the line protocol
+        match self {
+            Request::Tables => "tables".to_string(),
+            Request::Create { name, seed: None } => format!("create {}", name),
+            Request::Create {
+                name,
+                seed: Some(seed),
+            } => format!("create {} {}", name, seed),
+            Request::Join { name, seat } => format!("join {} {}", name, seat),
+            Request::Leave => "leave".to_string(),
+            Request::Sit { seat } => format!("sit {}", seat),
+            Request::Swap { seat } => format!("swap {}", seat),
+            Request::TeamName { name } => format!("teamname {}", name),
+            Request::Ready => "ready".to_string(),
+            Request::Start => "start".to_string(),
+            Request::Reconnect { token } => format!("reconnect {}", token),
+            Request::Watch { name, view: None } => format!("watch {}", name),
+            Request::Watch {
+                name,
+                view: Some(view),
+            } => format!("watch {} {}", name, view),
+            Request::TakeCards => "takecards".to_string(),
+            Request::Hints => "hints".to_string(),
+            Request::GrandTichu => "grandtichu".to_string(),
+            Request::Tichu => "tichu".to_string(),
+            Request::Exchange { cards } => format!("exchange {}", join_ids(cards)),
+            Request::Play { cards, wish } => match wish {
+                Some(kind) => format!("play {} {}", join_ids(cards), kind),
+                None => format!("play {}", join_ids(cards)),
+            },
+            Request::Pass => "pass".to_string(),
+            Request::GiveDragon { player } => format!("givedragon {}", player),
+            Request::Rematch { vote } => format!("rematch {}", if *vote { "yes" } else { "no" }),
+            Request::Chat { to, text } => format!("chat {} {}", to, text),
+            Request::Emote { to, emote } => format!("emote {} {}", to, emote),
+            Request::Stats { username: None } => "stats".to_string(),
+            Request::Stats {
+                username: Some(username),
+            } => format!("stats {}", username),
+            Request::Leaderboard => "leaderboard".to_string(),
+        }
+    }
+}
+
+fn join_ids(ids: &[usize]) -> String {
+    let ids: Vec<String> = ids.iter().map(|i| i.to_string()).collect();
+    ids.join(" ")
+}
+
+pub fn parse_request(protocol: Protocol, line: &str) -> Result<String, String> {
+    // translates a message of the client into the line protocol
+    match protocol {
+        Protocol::Line => Ok(line.to_string()),
+        Protocol::Json => match serde_json::from_str::<Request>(line) {
+            Ok(request) => request.check_words().map(|_| request.to_line()),
+            Err(e) => Err(format!("invalid request: {}", e)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::{Color, RegularKind, SpecialKind};
+
+    #[test]
+    fn test_line_format() {
+        let cards = [
+            Card::regular(RegularKind::Five, Color::Red),
+            Card::special(SpecialKind::Dragon),
+        ];
+        assert_eq!(
+            Message::Push(
+                "newtrick",
+                Payload::trick(&Trick::from_cards(cards.to_vec()))
+            )
+            .to_line(),
+            "push:newtrick:Red Five,Dragon,"
+        );
+        let hand = [(0, &cards[0]), (3, &cards[1])];
+        assert_eq!(
+            Message::Ok(Payload::hand(&hand)).to_line(),
+            "ok:0 Red Five,3 Dragon,"
+        );
+        assert_eq!(
+            Message::Ok(Payload::cards_with_ids(&hand)).to_line(),
+            "ok:Red Five,Dragon,"
+        );
+        let hands: [&[Card]; 4] = [&cards, &[], &cards[1..], &[]];
+        assert_eq!(
+            Message::Push("hands", Payload::hands(&hands)).to_line(),
+            "push:hands:Red Five,Dragon,;;Dragon,;"
+        );
+        let chat = ChatData {
+            seat: 1,
+            to: "table".to_string(),
+            text: "well, well".to_string(),
+        };
+        assert_eq!(
+            Message::Push("chat", Payload::Chat(chat)).to_line(),
+            "push:chat:1,table,well, well"
+        );
+        let stats = PlayerStats {
+            games: 12,
+            wins: 7,
+            rounds: 40,
+            points: 1941,
+            tichus: 5,
+            tichus_made: 3,
+            grand_tichus: 1,
+            grand_tichus_made: 0,
+            double_victories: 2,
+            rating: 1531.6,
+        };
+        assert_eq!(
+            Message::Ok(Payload::stats("alice", &stats)).to_line(),
+            "ok:alice,12,7,3/5,0/1,2,48.5,1532"
+        );
+        assert_eq!(
+            Message::Ok(Payload::leaderboard(&[("alice".to_string(), stats)])).to_line(),
+            "ok:alice 1532 12"
+        );
+        let teams = vec!["alice & carol".to_string(), "the_bots".to_string()];
+        assert_eq!(
+            Message::Push("teams", Payload::Teams(teams)).to_line(),
+            "push:teams:alice & carol;the_bots"
+        );
+        assert_eq!(Message::Ok(Payload::None).to_line(), "ok:");
+        assert_eq!(Message::Err("nope".to_string()).to_line(), "err:nope");
+    }
+
+    #[test]
+    fn test_json_format() {
+        let cards = [
+            Card::regular(RegularKind::Five, Color::Red),
+            Card::special(SpecialKind::Dragon),
+        ];
+        let hand = [(0, &cards[0]), (3, &cards[1])];
+        let json: serde_json::Value =
+            serde_json::from_str(&Message::Ok(Payload::hand(&hand)).to_json()).unwrap();
+        assert_eq!(
+            json,
+            json!({"type": "response", "ok": true, "data": {"type": "hand", "value": [
+                {"id": 0, "kind": "Five", "color": "Red"},
+                {"id": 3, "kind": "Dragon", "color": null},
+            ]}})
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&Message::Ok(Payload::cards_with_ids(&hand)).to_json()).unwrap();
+        assert_eq!(json["data"]["type"], "cards");
+        assert_eq!(json["data"]["value"][1]["id"], 3);
+        let json: serde_json::Value =
+            serde_json::from_str(&Message::Push("turn", Payload::Seat(2)).to_json()).unwrap();
+        assert_eq!(
+            json,
+            json!({"type": "event", "topic": "turn", "data": {"type": "seat", "value": 2}})
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&Message::Push("yourturn", Payload::None).to_json()).unwrap();
+        assert_eq!(
+            json,
+            json!({"type": "event", "topic": "yourturn", "data": null})
+        );
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            parse_request(
+                Protocol::Json,
+                r#"{"command": "play", "cards": [0, 3], "wish": "Seven"}"#
+            ),
+            Ok("play 0 3 Seven".to_string())
+        );
+        assert_eq!(
+            parse_request(Protocol::Json, r#"{"command": "takecards"}"#),
+            Ok("takecards".to_string())
+        );
+        assert_eq!(
+            parse_request(Protocol::Json, r#"{"command": "create", "name": "t1"}"#),
+            Ok("create t1".to_string())
+        );
+        assert_eq!(
+            parse_request(
+                Protocol::Json,
+                r#"{"command": "create", "name": "t1", "seed": 42}"#
+            ),
+            Ok("create t1 42".to_string())
+        );
+        assert_eq!(
+            parse_request(
+                Protocol::Json,
+                r#"{"command": "watch", "name": "t1", "view": "delayed"}"#
+            ),
+            Ok("watch t1 delayed".to_string())
+        );
+        assert_eq!(
+            parse_request(
+                Protocol::Json,
+                r#"{"command": "chat", "to": "partner", "text": "go for it"}"#
+            ),
+            Ok("chat partner go for it".to_string())
+        );
+        assert_eq!(
+            parse_request(Protocol::Json, r#"{"command": "swap", "seat": 2}"#),
+            Ok("swap 2".to_string())
+        );
+        assert_eq!(
+            parse_request(Protocol::Json, r#"{"command": "ready"}"#),
+            Ok("ready".to_string())
+        );
+        assert_eq!(
+            parse_request(
+                Protocol::Json,
+                r#"{"command": "stats", "username": "alice"}"#
+            ),
+            Ok("stats alice".to_string())
+        );
+        assert!(parse_request(Protocol::Json, r#"{"command": "cheat"}"#).is_err());
+        // strings that would turn into more than one word of the line command
+        assert!(parse_request(
+            Protocol::Json,
+            r#"{"command": "play", "cards": [0], "wish": "Seven 5"}"#
+        )
+        .is_err());
+        assert!(parse_request(Protocol::Json, r#"{"command": "create", "name": "x 5"}"#).is_err());
+        assert!(parse_request(
+            Protocol::Json,
+            r#"{"command": "join", "name": "", "seat": 1}"#
+        )
+        .is_err());
+        assert!(
+            parse_request(Protocol::Json, r#"{"command": "teamname", "name": "a\tb"}"#).is_err()
+        );
+        assert_eq!(
+            parse_request(Protocol::Line, "pass"),
+            Ok("pass".to_string())
+        );
+    }
+
+    #[test]
+    fn test_handshake() {
+        assert_eq!(
+            handshake("alice\n"),
+            Ok((Protocol::Line, Login::Guest("alice".to_string())))
+        );
+        assert_eq!(
+            handshake("login alice secret"),
+            Ok((
+                Protocol::Line,
+                Login::Password("alice".to_string(), "secret".to_string())
+            ))
+        );
+        assert_eq!(
+            handshake("register alice secret"),
+            Ok((
+                Protocol::Line,
+                Login::Register("alice".to_string(), "secret".to_string())
+            ))
+        );
+        assert_eq!(
+            handshake(r#"{"protocol": "json", "version": 1, "username": "bob"}"#),
+            Ok((Protocol::Json, Login::Guest("bob".to_string())))
+        );
+        assert_eq!(
+            handshake(
+                r#"{"protocol": "json", "version": 1, "username": "bob", "password": "secret", "register": true}"#
+            ),
+            Ok((
+                Protocol::Json,
+                Login::Register("bob".to_string(), "secret".to_string())
+            ))
+        );
+        assert!(handshake(r#"{"protocol": "json", "version": 2, "username": "bob"}"#).is_err());
+    }
+}
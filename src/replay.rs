@@ -0,0 +1,306 @@
+use crate::deck::Card;
+use crate::tichugame::{Deal, Event};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// version of the replay format, written to the first line of every replay
+pub const REPLAY_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Header {
+    replay: u32,
+    table: String,
+    players: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Replay {
+    pub table: String,
+    pub players: Vec<String>, // usernames by seat
+    pub events: Vec<Event>,
+}
+
+impl Replay {
+    pub fn new(table: &str, players: &[String], events: &[Event]) -> Replay {
+        Replay {
+            table: table.to_string(),
+            players: players.to_vec(),
+            events: events.to_vec(),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        // the file is called after the table and the time the game ended
+        fs::create_dir_all(dir)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let path = dir.join(format!("{}-{}.jsonl", self.table, time));
+        self.write(&mut File::create(&path)?)?;
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Replay::read(BufReader::new(file))
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // one json object per line, the header first and then the events
+        let header = Header {
+            replay: REPLAY_VERSION,
+            table: self.table.clone(),
+            players: self.players.clone(),
+        };
+        writeln!(out, "{}", serde_json::to_string(&header)?)?;
+        for event in &self.events {
+            writeln!(out, "{}", serde_json::to_string(event)?)?;
+        }
+        Ok(())
+    }
+
+    fn read<R: BufRead>(input: R) -> Result<Replay, String> {
+        let mut lines = input.lines().enumerate();
+        let header: Header = match lines.next() {
+            Some((_, Ok(line))) => {
+                serde_json::from_str(&line).map_err(|e| format!("this is not a replay: {}", e))?
+            }
+            Some((_, Err(e))) => return Err(e.to_string()),
+            None => return Err("the replay is empty".to_string()),
+        };
+        if header.replay != REPLAY_VERSION {
+            return Err(format!("unsupported replay version {}", header.replay));
+        }
+        if header.players.len() != 4 {
+            return Err("a replay needs four players".to_string());
+        }
+        let mut events = Vec::new();
+        for (n, line) in lines {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event =
+                serde_json::from_str(&line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            events.push(event);
+        }
+        Ok(Replay {
+            table: header.table,
+            players: header.players,
+            events,
+        })
+    }
+}
+
+pub struct Viewer<'a> {
+    // follows the events of a replay and keeps track of the hands and scores
+    players: &'a [String],
+    hands: [Vec<Card>; 4],
+    round: usize,
+    total: [i16; 2],
+}
+
+impl<'a> Viewer<'a> {
+    pub fn new(players: &'a [String]) -> Viewer<'a> {
+        Viewer {
+            players,
+            hands: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            round: 0,
+            total: [0, 0],
+        }
+    }
+
+    pub fn apply(&mut self, event: &Event) -> String {
+        // updates the state and describes what happened
+        let players = self.players;
+        let name = |i: usize| &players[i];
+        match event {
+            Event::Deal { seed, hands } => {
+                self.round += 1;
+                for (i, hand) in hands.iter().enumerate().take(4) {
+                    self.hands[i] = hand.clone();
+                }
+                match seed {
+                    Some(seed) => format!(
+                        "round {}: the cards are dealt with the seed {}",
+                        self.round, seed
+                    ),
+                    None => format!("round {}: the cards are dealt", self.round),
+                }
+            }
+            Event::GrandTichu { player } => format!("{} calls grand tichu", name(*player)),
+            Event::Tichu { player } => format!("{} calls tichu", name(*player)),
+            Event::Exchange { player, cards } => {
+                let description = format!(
+                    "{} gives {}",
+                    name(*player),
+                    cards
+                        .iter()
+                        .enumerate()
+                        .map(|(offset, card)| format!(
+                            "{} to {}",
+                            card,
+                            name((player + offset + 1) % 4)
+                        ))
+                        .join(", ")
+                );
+                for (offset, card) in cards.iter().enumerate() {
+                    self.remove(*player, card);
+                    self.hands[(player + offset + 1) % 4].push(*card);
+                }
+                description
+            }
+            Event::Play { player, cards } => {
+                for card in cards {
+                    self.remove(*player, card);
+                }
+                format!("{} plays {}", name(*player), cards.iter().join(", "))
+            }
+            Event::Wish { kind } => format!("the wish is {}", kind),
+            Event::Pass { player } => format!("{} passes", name(*player)),
+            Event::Trick { player, points } => {
+                format!("{} takes the trick ({} points)", name(*player), points)
+            }
+            Event::Dragon { player, to } => {
+                format!("{} gives the dragon to {}", name(*player), name(*to))
+            }
+            Event::Finish { player } => format!("{} is out of cards", name(*player)),
+            Event::Score { points } => {
+                self.total[0] += points[0];
+                self.total[1] += points[1];
+                format!(
+                    "round {} ends {}:{}, the score is {}:{}",
+                    self.round, points[0], points[1], self.total[0], self.total[1]
+                )
+            }
+        }
+    }
+
+    pub fn hand(&self, player: usize) -> &[Card] {
+        &self.hands[player]
+    }
+
+    pub fn hands(&self) -> String {
+        // one line per player with the cards they hold right now
+        (0..4)
+            .map(|i| format!("  {}: {}", self.players[i], self.hands[i].iter().join(", ")))
+            .join("\n")
+    }
+
+    fn remove(&mut self, player: usize, card: &Card) {
+        // a phoenix in a trick may have a different rank than the one in the hand
+        let hand = &mut self.hands[player];
+        if let Some(i) = hand
+            .iter()
+            .position(|c| c.kind == card.kind && c.color == card.color)
+        {
+            hand.remove(i);
+        }
+    }
+}
+
+pub fn show(path: &Path, step: bool) -> Result<(), String> {
+    // print the game event by event, when stepping wait for enter after each event
+    let replay = Replay::load(path)?;
+    println!(
+        "table {}: {} and {} against {} and {}",
+        replay.table, replay.players[0], replay.players[2], replay.players[1], replay.players[3]
+    );
+    let mut viewer = Viewer::new(&replay.players);
+    let stdin = io::stdin();
+    for event in &replay.events {
+        println!("{}", viewer.apply(event));
+        if step {
+            println!("{}", viewer.hands());
+            stdin
+                .lock()
+                .read_line(&mut String::new())
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn show_deal(seed: u64) {
+    // the cards that are dealt with this seed, in the order the players take them
+    for (i, hand) in Deal::shuffled(seed).hands.iter().enumerate() {
+        println!("{}: {}", i, hand.iter().join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::{Color, RegularKind, SpecialKind};
+
+    fn players() -> Vec<String> {
+        ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_write_read() {
+        let replay = Replay::new(
+            "t1",
+            &players(),
+            &[
+                Event::Tichu { player: 2 },
+                Event::Play {
+                    player: 0,
+                    cards: vec![
+                        Card::regular(RegularKind::Five, Color::Red),
+                        Card::special(SpecialKind::Phoenix),
+                    ],
+                },
+                Event::Wish {
+                    kind: RegularKind::Seven,
+                },
+                Event::Score { points: [120, -20] },
+            ],
+        );
+        let mut out = Vec::new();
+        replay.write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            vec![
+                r#"{"replay":1,"table":"t1","players":["a","b","c","d"]}"#,
+                r#"{"event":"tichu","player":2}"#,
+                r#"{"event":"play","player":0,"cards":["Red Five","Phoenix"]}"#,
+                r#"{"event":"wish","kind":"Seven"}"#,
+                r#"{"event":"score","points":[120,-20]}"#,
+            ]
+        );
+        assert_eq!(Replay::read(text.as_bytes()), Ok(replay));
+        assert!(Replay::read(&b"{\"replay\":2,\"table\":\"t\",\"players\":[]}"[..]).is_err());
+    }
+
+    #[test]
+    fn test_viewer() {
+        let players = players();
+        let mut viewer = Viewer::new(&players);
+        let five = Card::regular(RegularKind::Five, Color::Red);
+        let dragon = Card::special(SpecialKind::Dragon);
+        let hands = vec![vec![five, dragon], vec![], vec![], vec![]];
+        assert_eq!(
+            viewer.apply(&Event::Deal {
+                seed: Some(3),
+                hands
+            }),
+            "round 1: the cards are dealt with the seed 3"
+        );
+        viewer.apply(&Event::Play {
+            player: 0,
+            cards: vec![five],
+        });
+        assert_eq!(viewer.hands(), "  a: Dragon\n  b: \n  c: \n  d: ");
+        assert_eq!(
+            viewer.apply(&Event::Score { points: [150, -50] }),
+            "round 1 ends 150:-50, the score is 150:-50"
+        );
+    }
+}
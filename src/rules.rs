@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use toml::value::{Table, Value};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleSet {
+    // the variant of tichu that is played at every table of the server
+    pub target_score: i16, // the game is over once a team has this many points
+    pub grand_tichu: bool, // whether grand tichu can be called
+    pub exchange: bool,    // whether the players pass on three cards after the deal
+    pub phoenix: PhoenixRule,
+    pub bombs: BombTiming,
+    pub double_victory: i16, // points for a team whose players finish first and second
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PhoenixRule {
+    HalfAbove, // a phoenix singlet is worth half a rank more than the card it was played on
+    AboveAce,  // a phoenix singlet is worth 14.5, only the dragon tops it
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BombTiming {
+    AnyTime, // bombs can interrupt out of turn
+    OwnTurn, // bombs can only be played in the player's turn
+}
+
+impl Default for RuleSet {
+    fn default() -> RuleSet {
+        RuleSet {
+            target_score: 1000,
+            grand_tichu: true,
+            exchange: true,
+            phoenix: PhoenixRule::HalfAbove,
+            bombs: BombTiming::AnyTime,
+            double_victory: 200,
+        }
+    }
+}
+
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // like the command line, e.g. "target_score=1000,grand_tichu=true,...,bombs=any_time"
+        let phoenix = match self.phoenix {
+            PhoenixRule::HalfAbove => "half_above",
+            PhoenixRule::AboveAce => "above_ace",
+        };
+        let bombs = match self.bombs {
+            BombTiming::AnyTime => "any_time",
+            BombTiming::OwnTurn => "own_turn",
+        };
+        write!(
+            f,
+            "target_score={},grand_tichu={},exchange={},phoenix={},bombs={},double_victory={}",
+            self.target_score, self.grand_tichu, self.exchange, phoenix, bombs, self.double_victory
+        )
+    }
+}
+
+impl RuleSet {
+    pub fn load(path: Option<&Path>, overrides: &[&str]) -> Result<RuleSet, String> {
+        // the rules of a toml file (all of them are optional), then the ones given like
+        // "target_score=500" on the command line
+        let text = match path {
+            Some(path) => {
+                fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            None => String::new(),
+        };
+        RuleSet::parse(&text, overrides)
+    }
+
+    fn parse(text: &str, overrides: &[&str]) -> Result<RuleSet, String> {
+        let mut rules: Table = toml::from_str(text).map_err(|e| format!("invalid rules: {}", e))?;
+        for rule in overrides {
+            let (key, value) = match rule.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("{} is not of the form <rule>=<value>", rule)),
+            };
+            // values like own_turn don't need quotes
+            let value = match toml::from_str::<Table>(&format!("value = {}", value)) {
+                Ok(mut parsed) => parsed.remove("value").unwrap(),
+                Err(_) => Value::String(value.to_string()),
+            };
+            rules.insert(key.to_string(), value);
+        }
+        let rules: RuleSet = Value::Table(rules)
+            .try_into()
+            .map_err(|e| format!("invalid rules: {}", e))?;
+        if rules.target_score <= 0 {
+            return Err("the target score must be positive".to_string());
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(RuleSet::parse("", &[]), Ok(RuleSet::default()));
+        let text = "target_score = 500\nexchange = false\nphoenix = \"above_ace\"\n";
+        let rules = RuleSet::parse(text, &["target_score=600", "bombs = own_turn"]).unwrap();
+        assert_eq!(
+            rules,
+            RuleSet {
+                target_score: 600,
+                exchange: false,
+                phoenix: PhoenixRule::AboveAce,
+                bombs: BombTiming::OwnTurn,
+                ..RuleSet::default()
+            }
+        );
+        assert!(RuleSet::parse("target_score = 0", &[]).is_err());
+        assert!(RuleSet::parse("dragon = false", &[]).is_err());
+        assert!(RuleSet::parse("", &["bombs=never"]).is_err());
+        assert!(RuleSet::parse("", &["exchange"]).is_err());
+        // the rules that are announced can be given on the command line again
+        let announced = rules.to_string();
+        let overrides: Vec<&str> = announced.split(',').collect();
+        assert_eq!(RuleSet::parse("", &overrides), Ok(rules));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::player::Player;
+use crate::tichugame::TichuGame;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// version of the snapshot format, a snapshot of another version can't be resumed
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Seat {
+    pub username: String,
+    pub token: String, // lets the player take the seat again after losing the connection
+    pub bot: bool,
+}
+
+#[derive(Serialize)]
+pub struct Snapshot<'a> {
+    // everything that is needed to go on with the game at a table
+    snapshot: u32,
+    table: &'a str,
+    seats: &'a [Seat; 4],
+    players: [&'a Player; 4],
+    game: &'a TichuGame,
+    rematch_votes: [Option<bool>; 4],
+    rotated: Option<bool>, // set if the table plays the deals of a tournament
+}
+
+impl<'a> Snapshot<'a> {
+    pub fn new(
+        table: &'a str,
+        seats: &'a [Seat; 4],
+        players: [&'a Player; 4],
+        game: &'a TichuGame,
+        rematch_votes: [Option<bool>; 4],
+        rotated: Option<bool>,
+    ) -> Snapshot<'a> {
+        Snapshot {
+            snapshot: SNAPSHOT_VERSION,
+            table,
+            seats,
+            players,
+            game,
+            rematch_votes,
+            rotated,
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        // every table has one file that is replaced with each snapshot
+        let path = dir.join(format!("{}.json", self.table));
+        write_atomically(&path, &serde_json::to_string(self)?)?;
+        Ok(path)
+    }
+}
+
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    // the file is written next to the old one first, so that a crash while writing doesn't
+    // destroy what was saved before
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("part");
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, path)
+}
+
+pub fn remove(dir: &Path, table: &str) -> io::Result<()> {
+    // once the table is closed, there is nothing to resume
+    match fs::remove_file(dir.join(format!("{}.json", table))) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Restored {
+    // a snapshot that was read back from disk
+    snapshot: u32,
+    pub table: String,
+    pub seats: [Seat; 4],
+    pub players: [Player; 4],
+    pub game: TichuGame,
+    pub rematch_votes: [Option<bool>; 4],
+    pub rotated: Option<bool>,
+}
+
+impl Restored {
+    pub fn load(path: &Path) -> Result<Restored, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Restored::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(text: &str) -> Result<Restored, String> {
+        let mut restored: Restored =
+            serde_json::from_str(text).map_err(|e| format!("this is not a snapshot: {}", e))?;
+        if restored.snapshot != SNAPSHOT_VERSION {
+            return Err(format!(
+                "unsupported snapshot version {}",
+                restored.snapshot
+            ));
+        }
+        restored.game.reseed();
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn test_save_restore() {
+        let seats = [0, 1, 2, 3].map(|i| Seat {
+            username: format!("p{}", i),
+            token: format!("token{}", i),
+            bot: i == 3,
+        });
+        let mut players = [0, 1, 2, 3].map(|i| Player::new(format!("p{}", i)));
+        let mut game = TichuGame::new(7, RuleSet::default());
+        game.shuffle_and_deal();
+        for (i, player) in players.iter_mut().enumerate() {
+            player.take_new_hand(game.take_hand(i).unwrap());
+        }
+        let snapshot = Snapshot::new(
+            "t1",
+            &seats,
+            [&players[0], &players[1], &players[2], &players[3]],
+            &game,
+            [None, Some(true), None, None],
+            None,
+        );
+        let text = serde_json::to_string(&snapshot).unwrap();
+        let mut restored = Restored::parse(&text).unwrap();
+        assert_eq!(restored.table, "t1");
+        assert_eq!(restored.seats, seats);
+        assert_eq!(restored.rematch_votes, [None, Some(true), None, None]);
+        assert_eq!(restored.players[2].get_hand(), players[2].get_hand());
+        assert_eq!(restored.game.get_events(), game.get_events());
+        assert_eq!(restored.game.take_rest(1), game.take_rest(1));
+        // the next round is dealt with the same seed
+        game.shuffle_and_deal();
+        restored.game.shuffle_and_deal();
+        assert_eq!(restored.game.get_round_seeds(), game.get_round_seeds());
+        let old = text.replace("\"snapshot\":1", "\"snapshot\":2");
+        assert!(Restored::parse(&old).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::snapshot::write_atomically;
+use crate::tichugame::{Call, Event, TichuGame};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const INITIAL_RATING: f64 = 1500.0;
+// how much a single game can change the ratings
+const K_FACTOR: f64 = 32.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerStats {
+    pub games: u32,
+    pub wins: u32,
+    pub rounds: u32,
+    pub points: i32, // points of the player's team, summed over all rounds
+    pub tichus: u32,
+    pub tichus_made: u32,
+    pub grand_tichus: u32,
+    pub grand_tichus_made: u32,
+    pub double_victories: u32,
+    pub rating: f64,
+}
+
+impl PlayerStats {
+    fn new() -> PlayerStats {
+        PlayerStats {
+            games: 0,
+            wins: 0,
+            rounds: 0,
+            points: 0,
+            tichus: 0,
+            tichus_made: 0,
+            grand_tichus: 0,
+            grand_tichus_made: 0,
+            double_victories: 0,
+            rating: INITIAL_RATING,
+        }
+    }
+
+    pub fn average_points(&self) -> f64 {
+        if self.rounds == 0 {
+            0.0
+        } else {
+            f64::from(self.points) / f64::from(self.rounds)
+        }
+    }
+
+    fn add(&mut self, game: &PlayerStats) {
+        // the rating is not part of the numbers of a single game
+        self.games += game.games;
+        self.wins += game.wins;
+        self.rounds += game.rounds;
+        self.points += game.points;
+        self.tichus += game.tichus;
+        self.tichus_made += game.tichus_made;
+        self.grand_tichus += game.grand_tichus;
+        self.grand_tichus_made += game.grand_tichus_made;
+        self.double_victories += game.double_victories;
+    }
+}
+
+pub struct Stats {
+    // the statistics and ratings of every player who has finished a game, saved to a json file
+    path: PathBuf,
+    players: Mutex<BTreeMap<String, PlayerStats>>,
+}
+
+impl Stats {
+    pub fn load(path: &Path) -> Result<Stats, String> {
+        // the file is created after the first game
+        let players = match fs::read_to_string(path) {
+            Ok(text) => {
+                serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(Stats {
+            path: path.to_path_buf(),
+            players: Mutex::new(players),
+        })
+    }
+
+    pub fn get(&self, username: &str) -> Option<PlayerStats> {
+        self.players.lock().unwrap().get(username).cloned()
+    }
+
+    pub fn leaderboard(&self, n: usize) -> Vec<(String, PlayerStats)> {
+        // the n players with the highest rating
+        let players = self.players.lock().unwrap();
+        let mut ranking: Vec<(String, PlayerStats)> = players
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.clone()))
+            .collect();
+        ranking.sort_by(|a, b| b.1.rating.partial_cmp(&a.1.rating).unwrap());
+        ranking.truncate(n);
+        ranking
+    }
+
+    pub fn record(&self, players: [Option<&str>; 4], game: &TichuGame) {
+        // add a finished game to the statistics of the players, bots are given as None
+        let mut stats = self.players.lock().unwrap();
+        let ratings = players.map(|player| {
+            player
+                .and_then(|name| stats.get(name))
+                .map_or(INITIAL_RATING, |s| s.rating)
+        });
+        let changes = rating_changes(ratings, game.winner());
+        let tally = tally(game.get_events(), game.winner());
+        for (i, player) in players.iter().enumerate() {
+            if let Some(name) = player {
+                let entry = stats
+                    .entry(name.to_string())
+                    .or_insert_with(PlayerStats::new);
+                entry.add(&tally[i]);
+                entry.rating += changes[i % 2];
+            }
+        }
+        match self.save(&stats) {
+            Ok(_) => info!("saved the statistics to {}", self.path.display()),
+            Err(e) => error!("could not save the statistics: {}", e),
+        }
+    }
+
+    fn save(&self, players: &BTreeMap<String, PlayerStats>) -> io::Result<()> {
+        write_atomically(&self.path, &serde_json::to_string_pretty(players)?)
+    }
+}
+
+fn tally(events: &[Event], winner: Option<usize>) -> [PlayerStats; 4] {
+    // the numbers of every seat in a finished game, from its events
+    let mut tally = [0, 1, 2, 3].map(|i| PlayerStats {
+        games: 1,
+        wins: if winner == Some(i % 2) { 1 } else { 0 },
+        ..PlayerStats::new()
+    });
+    let mut calls = [None; 4];
+    let mut finished = Vec::new();
+    for event in events {
+        match event {
+            Event::Tichu { player } => calls[*player] = Some(Call::Tichu),
+            Event::GrandTichu { player } => calls[*player] = Some(Call::GrandTichu),
+            Event::Finish { player } => finished.push(*player),
+            Event::Score { points } => {
+                // a round ends after two finishers only if they are partners
+                let double_victory = finished.len() == 2;
+                for (i, seat) in tally.iter_mut().enumerate() {
+                    let made = finished.first() == Some(&i);
+                    seat.rounds += 1;
+                    seat.points += i32::from(points[i % 2]);
+                    match calls[i] {
+                        Some(Call::Tichu) => {
+                            seat.tichus += 1;
+                            seat.tichus_made += made as u32;
+                        }
+                        Some(Call::GrandTichu) => {
+                            seat.grand_tichus += 1;
+                            seat.grand_tichus_made += made as u32;
+                        }
+                        None => {}
+                    }
+                    if double_victory && finished[0] % 2 == i % 2 {
+                        seat.double_victories += 1;
+                    }
+                }
+                calls = [None; 4];
+                finished.clear();
+            }
+            _ => {}
+        }
+    }
+    tally
+}
+
+fn rating_changes(ratings: [f64; 4], winner: Option<usize>) -> [f64; 2] {
+    // elo for teams: each team plays with the average rating of its players, and both
+    // partners win or lose as much as their team. a draw counts half
+    let teams = [
+        (ratings[0] + ratings[2]) / 2.0,
+        (ratings[1] + ratings[3]) / 2.0,
+    ];
+    let expected = 1.0 / (1.0 + 10f64.powf((teams[1] - teams[0]) / 400.0));
+    let score = match winner {
+        Some(0) => 1.0,
+        Some(_) => 0.0,
+        None => 0.5,
+    };
+    let change = K_FACTOR * (score - expected);
+    [change, -change]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tally() {
+        let events = vec![
+            // player 1 makes their tichu, 0 and 2 fail
+            Event::GrandTichu { player: 0 },
+            Event::Tichu { player: 1 },
+            Event::Tichu { player: 2 },
+            Event::Finish { player: 1 },
+            Event::Finish { player: 0 },
+            Event::Finish { player: 2 },
+            Event::Score {
+                points: [-240, 240],
+            },
+            // double victory of 0 and 2
+            Event::Finish { player: 2 },
+            Event::Finish { player: 0 },
+            Event::Score { points: [200, 0] },
+        ];
+        let tally = tally(&events, Some(1));
+        assert_eq!(tally[1].wins, 1);
+        assert_eq!(tally[0].wins, 0);
+        assert_eq!((tally[1].tichus, tally[1].tichus_made), (1, 1));
+        assert_eq!((tally[2].tichus, tally[2].tichus_made), (1, 0));
+        assert_eq!((tally[0].grand_tichus, tally[0].grand_tichus_made), (1, 0));
+        assert_eq!(tally[3].tichus, 0);
+        assert_eq!(tally[0].double_victories, 1);
+        assert_eq!(tally[1].double_victories, 0);
+        assert_eq!(tally[2].rounds, 2);
+        assert_eq!(tally[2].points, -40);
+        assert_eq!(tally[2].average_points(), -20.0);
+    }
+
+    #[test]
+    fn test_rating_changes() {
+        let even = [INITIAL_RATING; 4];
+        assert_eq!(rating_changes(even, Some(0)), [16.0, -16.0]);
+        assert_eq!(rating_changes(even, None), [0.0, 0.0]);
+        // the weaker team wins more than it would lose
+        let uneven = [1400.0, 1600.0, 1400.0, 1600.0];
+        let won = rating_changes(uneven, Some(0))[0];
+        let lost = rating_changes(uneven, Some(1))[0];
+        assert!(won > 16.0 && lost > -16.0);
+        // only the average of a team counts
+        let mixed = [1300.0, 1600.0, 1500.0, 1600.0];
+        assert_eq!(
+            rating_changes(mixed, Some(0)),
+            rating_changes(uneven, Some(0))
+        );
+    }
+}
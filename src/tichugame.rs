@@ -1,57 +1,253 @@
 use crate::combinations::Trick;
-use crate::deck::{Card, Deck, SpecialKind};
+use crate::deck::{Card, Deck, Kind, RegularKind, SpecialKind};
+use crate::rules::{PhoenixRule, RuleSet};
 use log::debug;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct TichuGame {
-    deck: Deck,
-    // holds the hands that are meant for players after dealing, None as soon as a player takes theirs
+    seed: u64, // the same seed gives the same deals in every round
+    #[serde(default)]
+    rules: RuleSet,
+    // draws the seed of each round. it isn't part of a snapshot, reseed() restores it
+    #[serde(skip, default = "unseeded")]
+    seeds: ChaCha8Rng,
+    // the seeds the deck was shuffled with, one per round (None if the cards were given)
+    round_seeds: Vec<Option<u64>>,
+    // the cards of every round if they are given in advance, the game ends after the last one
+    deals: Option<Vec<Deal>>,
+    // holds the first eight cards that are meant for players after dealing, None as soon as a player takes theirs
     hands: [Option<Vec<Card>>; 4],
+    // the remaining six cards of each hand, players get them after they had the chance to call grand tichu
+    rest: [Option<Vec<Card>>; 4],
+    calls: [Option<Call>; 4], // tichu announcements of each player
+    has_played: [bool; 4], // whether a player has played a card in this round (tichu must be called before)
+    // cards that each player passes on, in the order left neighbour, partner, right neighbour
+    exchanges: [Option<Vec<Card>>; 4],
+    // cards that each player got from the others, None as soon as a player takes theirs
+    received: [Option<Vec<Card>>; 4],
+    exchanging: bool, // true as long as not all players have passed on their cards
     pub current_player: usize,
     player_points: [i16; 4],
     finished: Vec<usize>, // contains indices of players that finished, in order
-    tricks: Vec<Trick>, // tricks in the middle of the table
-    passes: u8, // number of times that players have passed (at 3, player wins the round)
+    tricks: Vec<Trick>,   // tricks in the middle of the table
+    trick_owner: usize,   // player who played the latest trick
+    passes: u8,           // number of times that players have passed (at 3, player wins the round)
+    wish: Option<RegularKind>, // the kind that was wished for with the one, until someone plays it
+    dragon_gift: Option<usize>, // player who won a trick with the dragon and has to give it away
     // scores[i][0] is for team 0,2 and scores[i][1] is for team 1,3
     scores: Vec<Vec<i16>>,
+    events: Vec<Event>, // everything that happened in this game, in order
 }
 
 impl TichuGame {
-    pub fn new() -> TichuGame {
+    pub fn new(seed: u64, rules: RuleSet) -> TichuGame {
         TichuGame {
-            deck: Deck::new(),
+            seed,
+            rules,
+            seeds: ChaCha8Rng::seed_from_u64(seed),
+            round_seeds: Vec::new(),
+            deals: None,
             hands: [None, None, None, None],
+            rest: [None, None, None, None],
+            calls: [None, None, None, None],
+            has_played: [false, false, false, false],
+            exchanges: [None, None, None, None],
+            received: [None, None, None, None],
+            exchanging: false,
             current_player: 0,
             player_points: [0, 0, 0, 0],
             passes: 0,
+            wish: None,
+            dragon_gift: None,
             finished: Vec::new(),
             scores: vec![vec![0, 0]],
             tricks: Vec::new(),
+            trick_owner: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn with_deals(seed: u64, deals: Vec<Deal>, rules: RuleSet) -> TichuGame {
+        // a game that is dealt the given cards, one deal per round
+        let mut game = TichuGame::new(seed, rules);
+        game.deals = Some(deals);
+        game
+    }
+
+    pub fn reseed(&mut self) {
+        // after loading a snapshot, the generator draws the same seeds as before
+        self.seeds = ChaCha8Rng::seed_from_u64(self.seed);
+        if self.deals.is_none() {
+            for _ in &self.round_seeds {
+                self.seeds.gen::<u64>();
+            }
         }
     }
 
     pub fn shuffle_and_deal(&mut self) {
-        self.deck.shuffle();
-        let hands = self.deck.deal();
-        for i in 0..4 {
-            self.hands[i] = Some(hands[i].to_vec());
+        let deal = match &self.deals {
+            Some(deals) => deals[self.round_seeds.len()].clone(),
+            None => Deal::shuffled(self.seeds.gen()),
+        };
+        let Deal { seed, hands } = deal;
+        self.round_seeds.push(seed);
+        debug!(
+            "round {} is dealt with the seed {:?}",
+            self.round_seeds.len(),
+            seed
+        );
+        self.events.push(Event::Deal {
+            seed,
+            hands: hands.to_vec(),
+        });
+        for (i, hand) in hands.iter().enumerate() {
+            // the player with the one starts (this may change during the exchange)
+            if hand.contains(&Card::special(SpecialKind::One)) {
+                self.current_player = i;
+            }
+            self.hands[i] = Some(hand[..8].to_vec());
+            self.rest[i] = Some(hand[8..].to_vec());
         }
+        self.calls = [None, None, None, None];
+        self.has_played = [false, false, false, false];
+        self.wish = None;
+        self.dragon_gift = None;
+        self.finished = Vec::new();
+        self.tricks = Vec::new();
+        self.passes = 0;
+        self.player_points = [0, 0, 0, 0];
+        self.exchanges = [None, None, None, None];
+        self.received = [None, None, None, None];
+        self.exchanging = true;
     }
 
     pub fn take_hand(&mut self, i: usize) -> Option<Vec<Card>> {
         self.hands[i].take()
     }
 
+    pub fn take_rest(&mut self, i: usize) -> Option<Vec<Card>> {
+        // the rest can only be taken after the first eight cards. without an exchange, the
+        // round starts as soon as everyone has all their cards
+        if self.hands[i].is_some() {
+            return None;
+        }
+        let rest = self.rest[i].take();
+        if !self.rules.exchange && (0..4).all(|i| self.has_all_cards(i)) {
+            self.exchanging = false;
+        }
+        rest
+    }
+
+    pub fn has_all_cards(&self, i: usize) -> bool {
+        self.hands[i].is_none() && self.rest[i].is_none()
+    }
+
+    pub fn call_grand_tichu(&mut self, i: usize) -> bool {
+        // grand tichu can only be called after seeing the first eight cards
+        if !self.rules.grand_tichu
+            || self.calls[i].is_some()
+            || self.hands[i].is_some()
+            || self.rest[i].is_none()
+        {
+            return false;
+        }
+        self.calls[i] = Some(Call::GrandTichu);
+        self.events.push(Event::GrandTichu { player: i });
+        true
+    }
+
+    pub fn call_tichu(&mut self, i: usize) -> bool {
+        // tichu can be called once the player has all cards, until they play their first
+        // card. before that, only grand tichu can be called
+        if self.calls[i].is_some() || self.has_played[i] || !self.has_all_cards(i) {
+            return false;
+        }
+        self.calls[i] = Some(Call::Tichu);
+        self.events.push(Event::Tichu { player: i });
+        true
+    }
+
+    pub fn exchange(&mut self, i: usize, cards: Vec<Card>) -> bool {
+        // player i passes cards[0] to the left neighbour, cards[1] to the partner and cards[2]
+        // to the right neighbour. returns true if this was the last missing player
+        if !self.rules.exchange {
+            return false;
+        }
+        self.events.push(Event::Exchange {
+            player: i,
+            cards: cards.clone(),
+        });
+        self.exchanges[i] = Some(cards);
+        if self.exchanges.iter().any(|e| e.is_none()) {
+            return false;
+        }
+        for receiver in 0..4 {
+            // collect the cards in the order left neighbour, partner, right neighbour
+            let mut cards = Vec::new();
+            for offset in 1..4 {
+                let giver = (receiver + offset) % 4;
+                // the giver's slot that is meant for the receiver
+                let slot = (receiver + 3 - giver) % 4;
+                let card = self.exchanges[giver].as_ref().unwrap()[slot];
+                if card == Card::special(SpecialKind::One) {
+                    self.current_player = receiver;
+                }
+                cards.push(card);
+            }
+            self.received[receiver] = Some(cards);
+        }
+        self.exchanging = false;
+        true
+    }
+
+    pub fn has_exchanged(&self, i: usize) -> bool {
+        self.exchanges[i].is_some()
+    }
+
+    pub fn exchange_complete(&self) -> bool {
+        !self.exchanging
+    }
+
+    pub fn take_received(&mut self, i: usize) -> Option<Vec<Card>> {
+        self.received[i].take()
+    }
+
+    pub fn peek_received(&self, i: usize) -> Option<&Vec<Card>> {
+        self.received[i].as_ref()
+    }
+
     pub fn pass(&mut self) {
         // call this if a player doesn't want to play
+        self.events.push(Event::Pass {
+            player: self.current_player,
+        });
         self.passes += 1;
     }
 
-    pub fn add_trick(&mut self, trick: Trick) {
+    pub fn add_trick(&mut self, mut trick: Trick) {
         // players must make sure themselves that trick is valid
+        if self.rules.phoenix == PhoenixRule::AboveAce && trick.phoenix_rank.is_some() {
+            trick.phoenix_rank = Some(14.5);
+        }
         self.passes = 0; // chain of passes is interrupted
+        self.has_played[self.current_player] = true;
+        self.trick_owner = self.current_player;
+        self.events.push(Event::Play {
+            player: self.current_player,
+            cards: trick.cards.clone(),
+        });
         self.tricks.push(trick);
     }
 
+    pub fn add_bomb(&mut self, player_index: usize, trick: Trick) {
+        // bombs can be played out of turn, the bomber takes over the turn
+        self.current_player = player_index;
+        self.add_trick(trick);
+    }
+
     pub fn next(&mut self) -> RoundStatus {
         // move current player
         // if the latest trick is the dog, the current player shifts by 2
@@ -68,13 +264,22 @@ impl TichuGame {
             if self.passes == 3 {
                 // if 3 players pass, the current player wins this round
                 self.passes = 0;
-                // collect all the points
-                for trick in &self.tricks {
-                    debug!("player {} wins and gets {} points", self.current_player, trick.points());
-                    self.player_points[self.current_player] += trick.points();
+                let mut dragon = Trick::new();
+                dragon.push(Card::special(SpecialKind::Dragon));
+                if self.tricks.last() == Some(&dragon) {
+                    // a trick won with the dragon must be given to an opponent, the points
+                    // stay on the table until the winner has decided who gets them
+                    debug!(
+                        "player {} wins with the dragon and has to give it away",
+                        self.current_player
+                    );
+                    self.dragon_gift = Some(self.current_player);
+                    status = RoundStatus::DragonGift;
+                } else {
+                    // collect all the points
+                    self.collect_tricks(self.current_player);
+                    status = RoundStatus::TrickWin;
                 }
-                self.tricks = Vec::new();
-                status = RoundStatus::TrickWin;
             }
             // if the current player has no cards left, they "pass"
             if self.finished.contains(&self.current_player) {
@@ -85,8 +290,48 @@ impl TichuGame {
         status
     }
 
+    fn collect_tricks(&mut self, player_index: usize) {
+        let points: i16 = self.tricks.iter().map(|trick| trick.points()).sum();
+        debug!("player {} gets {} points", player_index, points);
+        self.player_points[player_index] += points;
+        self.events.push(Event::Trick {
+            player: player_index,
+            points,
+        });
+        self.tricks = Vec::new();
+    }
+
+    pub fn give_dragon(&mut self, player_index: usize, opponent: usize) -> bool {
+        // give the trick won with the dragon to an opponent, returns false if
+        // player_index has nothing to give or opponent is not an opponent
+        if self.dragon_gift != Some(player_index)
+            || opponent > 3
+            || player_index % 2 == opponent % 2
+        {
+            return false;
+        }
+        self.events.push(Event::Dragon {
+            player: player_index,
+            to: opponent,
+        });
+        self.collect_tricks(opponent);
+        self.dragon_gift = None;
+        true
+    }
+
+    pub fn get_call(&self, i: usize) -> Option<Call> {
+        self.calls[i]
+    }
+
+    pub fn get_dragon_gift(&self) -> Option<usize> {
+        self.dragon_gift
+    }
+
     pub fn mark_finished(&mut self, player_index: usize) -> RoundStatus {
         self.finished.push(player_index);
+        self.events.push(Event::Finish {
+            player: player_index,
+        });
         let mut gamestatus: RoundStatus;
         // if only one player is left, the round has ended
         if self.finished.len() == 3 {
@@ -104,13 +349,21 @@ impl TichuGame {
             points[self.finished[2] % 2] += self.player_points[self.finished[2]];
             // left cards of the last player go to opposing team. the value of the left cards is 100 - sum(points)
             points[(last + 1) % 2] += 100 - points.iter().sum::<i16>();
+            self.add_call_points(&mut points);
             // save and reset
+            self.events.push(Event::Score {
+                points: [points[0], points[1]],
+            });
             self.scores.push(points);
             self.player_points = [0, 0, 0, 0];
             gamestatus = RoundStatus::FinishRound;
         } else if self.finished.len() == 2 && (self.finished[0] % 2 == self.finished[1] % 2) {
             let mut points = vec![0, 0];
-            points[self.finished[0] % 2] += 200;
+            points[self.finished[0] % 2] += self.rules.double_victory;
+            self.add_call_points(&mut points);
+            self.events.push(Event::Score {
+                points: [points[0], points[1]],
+            });
             self.scores.push(points);
             self.player_points = [0, 0, 0, 0];
             gamestatus = RoundStatus::FinishRound;
@@ -118,33 +371,310 @@ impl TichuGame {
             gamestatus = RoundStatus::Continue;
         }
         // check if game is over
-        if self.scores[self.scores.len() - 1][0] > 999 {
-            gamestatus = RoundStatus::Team1Wins;
-        } else if self.scores[self.scores.len() - 1][1] > 999 {
-            gamestatus = RoundStatus::Team2Wins;
+        if self.is_over() {
+            gamestatus = match self.winner() {
+                Some(0) => RoundStatus::Team1Wins,
+                Some(_) => RoundStatus::Team2Wins,
+                None => RoundStatus::Draw,
+            };
+        }
+        gamestatus
+    }
+
+    fn all_deals_played(&self) -> bool {
+        // the first entry of the scores is the empty score at the beginning
+        match &self.deals {
+            Some(deals) => self.scores.len() > deals.len(),
+            None => false,
+        }
+    }
+
+    pub fn winner(&self) -> Option<usize> {
+        // the game is over as soon as a team has reached the target score, if both teams
+        // have reached it, the one with more points wins (and nobody on a tie).
+        // with given deals, the game is over after the last one
+        let (team1, team2) = self.get_total_score();
+        let finished = match self.deals {
+            Some(_) => self.all_deals_played(),
+            None => team1.max(team2) >= self.rules.target_score,
+        };
+        if finished && team1 != team2 {
+            Some(if team1 > team2 { 0 } else { 1 })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.winner().is_some() || self.all_deals_played()
+    }
+
+    fn add_call_points(&self, points: &mut [i16]) {
+        // a successful tichu gives 100 points (grand tichu 200), a failed one costs as much
+        for (i, call) in self.calls.iter().enumerate() {
+            if let Some(call) = call {
+                let bonus = match call {
+                    Call::Tichu => 100,
+                    Call::GrandTichu => 200,
+                };
+                if self.finished[0] == i {
+                    points[i % 2] += bonus;
+                } else {
+                    points[i % 2] -= bonus;
+                }
+            }
+        }
+    }
+
+    pub fn make_wish(&mut self, kind: RegularKind) {
+        self.events.push(Event::Wish { kind });
+        self.wish = Some(kind);
+    }
+
+    pub fn get_wish(&self) -> Option<RegularKind> {
+        self.wish
+    }
+
+    pub fn fulfil_wish(&mut self, trick: &Trick) -> bool {
+        // clear the wish if trick contains the wished kind, returns true if it did
+        match self.wish {
+            Some(kind) if trick.contains_kind(Kind::Regular(kind)) => {
+                self.wish = None;
+                true
+            }
+            _ => false,
         }
-        return gamestatus;
     }
 
     pub fn get_current_trick(&self) -> Option<&Trick> {
-        if self.tricks.len() > 0 {
+        if !self.tricks.is_empty() {
             Some(&self.tricks[self.tricks.len() - 1])
         } else {
             None
         }
     }
 
+    pub fn get_trick_owner(&self) -> Option<usize> {
+        // the player who is about to win the tricks on the table
+        self.get_current_trick().map(|_| self.trick_owner)
+    }
+
     pub fn get_current_score(&self) -> (i16, i16) {
         let points = &self.scores[self.scores.len() - 1];
         (points[0], points[1])
     }
+
+    pub fn get_total_score(&self) -> (i16, i16) {
+        self.scores
+            .iter()
+            .fold((0, 0), |acc, points| (acc.0 + points[0], acc.1 + points[1]))
+    }
+
+    pub fn get_scores(&self) -> &[Vec<i16>] {
+        // the points of every round, the first entry is the empty score at the beginning
+        &self.scores
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_round_seeds(&self) -> &[Option<u64>] {
+        &self.round_seeds
+    }
+
+    pub fn get_events(&self) -> &[Event] {
+        &self.events
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Call {
+    Tichu,
+    GrandTichu,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    // the entries of a game's log, players are given by their seat
+    // all 14 cards of each player, the first eight come first. the seed is missing if the cards
+    // were given in advance
+    Deal {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seed: Option<u64>,
+        hands: Vec<Vec<Card>>,
+    },
+    GrandTichu {
+        player: usize,
+    },
+    Tichu {
+        player: usize,
+    },
+    Exchange {
+        player: usize,
+        cards: Vec<Card>,
+    }, // for the left neighbour, partner, right neighbour
+    Play {
+        player: usize,
+        cards: Vec<Card>,
+    }, // also bombs played out of turn
+    Wish {
+        kind: RegularKind,
+    },
+    Pass {
+        player: usize,
+    },
+    Trick {
+        player: usize,
+        points: i16,
+    }, // player takes the tricks on the table
+    Dragon {
+        player: usize,
+        to: usize,
+    }, // player gives the tricks won with the dragon away
+    Finish {
+        player: usize,
+    }, // player has no cards left
+    Score {
+        points: [i16; 2],
+    }, // the points of a finished round
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Deal {
+    // the cards of one round
+    pub seed: Option<u64>, // None if the cards weren't shuffled with a seed
+    pub hands: [Vec<Card>; 4],
+}
+
+impl Deal {
+    pub fn shuffled(seed: u64) -> Deal {
+        // the deal only depends on the seed, the deck is shuffled from the new order
+        let mut deck = Deck::new();
+        deck.shuffle(seed);
+        Deal {
+            seed: Some(seed),
+            hands: deck.deal(),
+        }
+    }
+}
+
+fn unseeded() -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(0)
 }
 
 #[derive(PartialEq, Eq)]
 pub enum RoundStatus {
     Continue,
-    TrickWin, // someone's won a trick but the round continues
+    TrickWin,    // someone's won a trick but the round continues
+    DragonGift,  // someone's won a trick with the dragon and has to give it away
     FinishRound, // round's finished
     Team1Wins,
     Team2Wins,
+    Draw, // the given deals are played and both teams have the same score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::Color;
+
+    #[test]
+    fn test_seeded_deals() {
+        // two games with the same seed deal the same cards in every round
+        let mut game = TichuGame::new(7, RuleSet::default());
+        let mut same = TichuGame::new(7, RuleSet::default());
+        for _ in 0..3 {
+            game.shuffle_and_deal();
+            same.shuffle_and_deal();
+        }
+        assert_eq!(game.get_events(), same.get_events());
+        assert_eq!(game.get_round_seeds(), same.get_round_seeds());
+        assert_eq!(game.get_round_seeds().len(), 3);
+        // each deal can be regenerated from the seed of its round
+        for event in game.get_events() {
+            if let Event::Deal { seed, hands } = event {
+                assert_eq!(hands, &Deal::shuffled(seed.unwrap()).hands.to_vec());
+            }
+        }
+    }
+
+    #[test]
+    fn test_calls() {
+        let mut game = TichuGame::new(5, RuleSet::default());
+        game.shuffle_and_deal();
+        // tichu needs all fourteen cards, grand tichu only the first eight
+        assert!(!game.call_tichu(0));
+        game.take_hand(0);
+        assert!(!game.call_tichu(0));
+        assert!(game.call_grand_tichu(0));
+        game.take_hand(1);
+        game.take_rest(1);
+        assert!(!game.call_grand_tichu(1));
+        assert!(game.call_tichu(1));
+        assert!(!game.call_tichu(1));
+    }
+
+    #[test]
+    fn test_given_deals() {
+        let deals = vec![Deal::shuffled(1), Deal::shuffled(2)];
+        let mut game = TichuGame::with_deals(0, deals.clone(), RuleSet::default());
+        for deal in &deals {
+            game.shuffle_and_deal();
+            assert_eq!(game.take_hand(1), Some(deal.hands[1][..8].to_vec()));
+            // a double victory ends the round
+            game.mark_finished(0);
+            assert!(!game.is_over());
+            let status = game.mark_finished(2);
+            assert!(status == RoundStatus::FinishRound || status == RoundStatus::Team1Wins);
+        }
+        // the game ends after the last deal, even without 1000 points
+        assert!(game.is_over());
+        assert_eq!(game.winner(), Some(0));
+        assert_eq!(game.get_total_score(), (400, 0));
+        assert_eq!(game.get_round_seeds(), &[Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn test_rule_variants() {
+        let rules = RuleSet {
+            target_score: 300,
+            grand_tichu: false,
+            exchange: false,
+            double_victory: 300,
+            ..RuleSet::default()
+        };
+        let mut game = TichuGame::new(3, rules);
+        game.shuffle_and_deal();
+        for i in 0..4 {
+            assert!(game.take_hand(i).is_some());
+            assert!(!game.call_grand_tichu(i));
+            assert!(!game.exchange_complete());
+            assert!(game.take_rest(i).is_some());
+        }
+        // the round starts right away once everyone has their cards
+        assert!(game.exchange_complete());
+        assert!(!game.exchange(0, vec![]));
+        game.mark_finished(1);
+        let status = game.mark_finished(3);
+        assert!(status == RoundStatus::Team2Wins);
+        assert_eq!(game.get_total_score(), (0, 300));
+        // only the dragon tops the phoenix
+        let rules = RuleSet {
+            phoenix: PhoenixRule::AboveAce,
+            ..RuleSet::default()
+        };
+        let mut game = TichuGame::new(3, rules);
+        let five = Trick::from_cards(vec![Card::regular(RegularKind::Five, Color::Red)]);
+        let mut phoenix = Trick::from_cards(vec![Card::special(SpecialKind::Phoenix)]);
+        phoenix.set_phoenix_rank(Some(&five));
+        game.add_trick(five);
+        game.add_trick(phoenix);
+        assert_eq!(game.get_current_trick().unwrap().phoenix_rank, Some(14.5));
+    }
 }
@@ -1,187 +1,870 @@
-use crate::deck::{Card, SpecialKind};
+use crate::chat::{self, Chat};
+use crate::deck::{Card, RegularKind, SpecialKind};
+use crate::lobby::{Lobby, Settings, Visitor};
 use crate::player::{Player, PlayerError};
-use crate::tichugame::{TichuGame, RoundStatus};
-use bufstream::BufStream;
+use crate::protocol::{self, Login, Message, Payload, Protocol, VoteData};
+use crate::replay::{Replay, Viewer};
+use crate::rules::BombTiming;
+use crate::snapshot::{self, Restored, Seat, Snapshot};
+use crate::stats::Stats;
+use crate::tichugame::{Call, RoundStatus, TichuGame};
+use crate::timer::{self, Alarm, Timer};
+use crate::tournament::Entry;
+use crate::transport::{Client, Incoming};
 use log::{debug, error, info, warn};
-use std::io::{BufRead, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
+use std::time::Instant;
 
-struct TichuConnection {
+pub struct TichuConnection {
     game: Mutex<TichuGame>,
-    streams: [Mutex<TcpStream>; 4],
+    // the players live here rather than in the connection threads so that they survive
+    // a dropped connection
+    players: [Mutex<Player>; 4],
+    streams: [Mutex<Client>; 4],
+    // votes for a rematch once the game is over, a rematch needs all four players to agree
+    rematch_votes: Mutex<[Option<bool>; 4]>,
+    closed: AtomicBool, // true once the game has ended for good
+    table: String,
+    seats: [Seat; 4],
+    usernames: [String; 4],
+    replays: PathBuf,          // directory for the replays of finished games
+    snapshots: PathBuf,        // directory for the snapshot of the game
+    tournament: Option<Entry>, // the table plays the deals of a tournament, there's no rematch
+    stats: Arc<Stats>,         // every finished game counts for the statistics of the players
+    spectators: Mutex<Vec<Option<Spectator>>>, // indexed by the id that watch() returns
+    spectated: AtomicUsize,    // number of events that the spectators know the hands of
+    timer: Mutex<Timer>,       // deadlines of the players who have to act
+    // set while the server acts for a player who ran out of time, the answers are not sent
+    automatic: [AtomicBool; 4],
+}
+
+struct Spectator {
+    stream: Client,
+    hands: bool, // sees the cards of all players
 }
 
 impl TichuConnection {
-    pub fn new(connections: [Mutex<TcpStream>; 4]) -> TichuConnection {
+    pub fn new(
+        connections: [Mutex<Client>; 4],
+        seats: [Seat; 4],
+        table: &str,
+        settings: &Settings,
+        seed: u64,
+        tournament: Option<Entry>,
+    ) -> TichuConnection {
+        let game = match &tournament {
+            Some(entry) => TichuGame::with_deals(seed, entry.deals(), settings.rules.clone()),
+            None => TichuGame::new(seed, settings.rules.clone()),
+        };
+        let players = seats.clone().map(|seat| Player::new(seat.username));
+        TichuConnection::with_state(
+            connections,
+            seats,
+            table,
+            settings,
+            game,
+            players,
+            tournament,
+        )
+    }
+
+    pub fn restore(
+        connections: [Mutex<Client>; 4],
+        restored: Restored,
+        settings: &Settings,
+        tournament: Option<Entry>,
+    ) -> TichuConnection {
+        // the game of a snapshot, call resume() once the bots are there
+        let connection = TichuConnection::with_state(
+            connections,
+            restored.seats,
+            &restored.table,
+            settings,
+            restored.game,
+            restored.players,
+            tournament,
+        );
+        *connection.rematch_votes.lock().unwrap() = restored.rematch_votes;
+        connection
+    }
+
+    fn with_state(
+        connections: [Mutex<Client>; 4],
+        seats: [Seat; 4],
+        table: &str,
+        settings: &Settings,
+        game: TichuGame,
+        players: [Player; 4],
+        tournament: Option<Entry>,
+    ) -> TichuConnection {
         TichuConnection {
-            game: Mutex::new(TichuGame::new()),
+            game: Mutex::new(game),
+            players: players.map(Mutex::new),
             streams: connections,
+            rematch_votes: Mutex::new([None, None, None, None]),
+            closed: AtomicBool::new(false),
+            table: table.to_string(),
+            usernames: seats.clone().map(|seat| seat.username),
+            seats,
+            replays: settings.replays.clone(),
+            snapshots: settings.snapshots.clone(),
+            tournament,
+            stats: settings.stats.clone(),
+            spectators: Mutex::new(Vec::new()),
+            spectated: AtomicUsize::new(0),
+            timer: Mutex::new(Timer::new(settings.limits)),
+            automatic: [
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+                AtomicBool::new(false),
+            ],
         }
     }
 
-    pub fn handle_connection(
-        &self,
-        player_index: usize,
-    ) {
-        // say hello (this message also tells the client that every other player is connected)
-        self.answer_ok(player_index);
-        // create a read-only bufstream of this connection's TcpStream
-        let mut readstream: BufStream<TcpStream>;
+    pub fn start(&self) {
+        // deal the cards and tell everyone where they sit
         {
-            let stream = self.streams[player_index].lock().unwrap();
-            // clone the stream because BufStream::new() and lines() take ownership
-            // use readstream to iterate over incoming lines, this has the advantage
-            // that it blocks the thread until there is a new line as opposed to
-            // loop { stream.read() }.
-            readstream = BufStream::new(stream.try_clone().unwrap());
-        }
-        // get the username first and create a player instance
-        let mut username = String::new();
-        readstream.read_line(&mut username).unwrap();
-        let mut player = Player::new(username.trim().to_string());
+            let mut game = self.game.lock().unwrap();
+            game.shuffle_and_deal();
+            self.timer.lock().unwrap().start_exchange(Instant::now());
+            // the rules come first, so that the players know them before they see their cards
+            self.send_push_to_all("rules", Payload::Rules(game.rules().clone()));
+            for i in 0..4 {
+                self.send_push(i, "start", Payload::Seat(i));
+            }
+        }
+        self.save_snapshot();
+    }
 
-        // main loop waiting for commands
-        for line in readstream.lines() {
-            if let Ok(msg) = line {
-                debug!("got message from {}: {}", player.username, msg);
-                // check for all the possible messages
-                if msg == "takecards" {
-                    // acquire the lock to self.game
-                    let mut game = self.game.lock().unwrap();
-                    match game.take_hand(player_index) {
-                        Some(h) => {
-                            self.answer_msg(player_index, &format_hand(&h));
-                            // check if this player has the One
-                            if h.contains(&Card::special(SpecialKind::One)) {
-                                game.current_player = player_index;
-                                self.send_push(player_index, "yourturn", "");
+    pub fn resume(&self) {
+        // go on with a restored game: the bots catch up like reconnecting players (during the
+        // exchange, they start over) and the clocks start again
+        for i in 0..4 {
+            if self.seats[i].bot {
+                let client = self.streams[i].lock().unwrap().clone();
+                self.reconnect(i, client);
+                let game = self.game.lock().unwrap();
+                if !game.exchange_complete() && !game.has_exchanged(i) {
+                    self.send_push(i, "start", Payload::Seat(i));
+                }
+            }
+        }
+        let game = self.game.lock().unwrap();
+        let mut timer = self.timer.lock().unwrap();
+        let now = Instant::now();
+        if game.is_over() {
+            timer.stop_all();
+        } else if !game.exchange_complete() {
+            timer.start_exchange(now);
+            for i in (0..4).filter(|i| game.has_exchanged(*i)) {
+                timer.stop(i);
+            }
+        } else if let Some(winner) = game.get_dragon_gift() {
+            timer.start_turn(winner, now);
+        } else {
+            timer.start_turn(game.current_player, now);
+        }
+    }
+
+    pub fn reconnect(&self, player_index: usize, client: Client) {
+        // the player comes back with a new connection, bring them up to date.
+        // always lock the player before the game, like handle_message does
+        let mut player = self.players[player_index].lock().unwrap();
+        let mut game = self.game.lock().unwrap();
+        {
+            let mut old = self.streams[player_index].lock().unwrap();
+            // make sure the old connection is gone for good, its thread then returns to the lobby
+            old.shutdown();
+            *old = client;
+        }
+        info!("{} reconnected to seat {}", player.username, player_index);
+        pick_up_received(&mut player, &mut game, player_index);
+        self.answer_msg(player_index, Payload::Seat(player_index));
+        // hand, calls, current trick, wish, scores and turn
+        self.send_push(player_index, "hand", Payload::hand(&player.get_hand()));
+        for (topic, payload) in public_state(&game) {
+            self.send_push(player_index, topic, payload);
+        }
+        if game.is_over() {
+            self.send_push(player_index, "gameover", Payload::scores(&game));
+        } else if game.get_dragon_gift() == Some(player_index) {
+            self.send_push(player_index, "givedragon", Payload::None);
+        } else if game.current_player == player_index
+            && game.exchange_complete()
+            && game.get_dragon_gift().is_none()
+        {
+            self.send_push(player_index, "yourturn", Payload::None);
+        }
+    }
+
+    pub fn watch(&self, client: Client, hands: bool) -> usize {
+        // a spectator gets the state of the game and from then on every public push,
+        // returns the id of the spectator
+        let game = self.game.lock().unwrap();
+        let mut spectator = Spectator {
+            stream: client,
+            hands,
+        };
+        let seats = self.usernames.iter().cloned().map(Some).collect();
+        let mut pushes = vec![("seats", Payload::Seats(seats))];
+        pushes.extend(public_state(&game));
+        if hands {
+            pushes.push(("hands", self.hands(&game)));
+        }
+        if game.is_over() {
+            pushes.push(("gameover", Payload::scores(&game)));
+        }
+        for (topic, payload) in pushes {
+            spectator.stream.send(&Message::Push(topic, payload));
+        }
+        let mut spectators = self.spectators.lock().unwrap();
+        spectators.push(Some(spectator));
+        spectators.len() - 1
+    }
+
+    pub fn unwatch(&self, id: usize) {
+        if let Some(spectator) = self.spectators.lock().unwrap().get_mut(id) {
+            *spectator = None;
+        }
+    }
+
+    pub fn chat(&self, player_index: usize, chat: &Chat) {
+        // messages to the whole table reach the spectators too
+        let payload = chat.payload(player_index);
+        if chat.partner {
+            self.send_push(player_index, chat.topic, payload.clone());
+            self.send_push((player_index + 2) % 4, chat.topic, payload);
+        } else {
+            self.send_push_to_all(chat.topic, payload);
+        }
+    }
+
+    pub fn check_timer(&self, now: Instant) {
+        // warn the players who are running out of time and act for those whose time is up
+        let alarms = self.timer.lock().unwrap().poll(now);
+        for alarm in alarms {
+            match alarm {
+                Alarm::Warning(seat, seconds) => {
+                    self.send_push(seat, "timer", Payload::Seconds(seconds))
+                }
+                Alarm::Expired(seat) => self.time_out(seat),
+            }
+        }
+    }
+
+    fn time_out(&self, player_index: usize) {
+        // the server sends the default commands for the player, who gets their new hand
+        // instead of the answers
+        let mut player = self.players[player_index].lock().unwrap();
+        self.automatic[player_index].store(true, Ordering::SeqCst);
+        loop {
+            let command = {
+                let mut game = self.game.lock().unwrap();
+                pick_up_received(&mut player, &mut game, player_index);
+                timer::default_command(&player, &game, player_index)
+            };
+            let command = match command {
+                Some(command) => command,
+                None => break,
+            };
+            info!(
+                "{} ran out of time, the server sends '{}' for them",
+                player.username, command
+            );
+            self.handle_command(&mut player, player_index, &command);
+            // the cards are taken before exchanging, everything else is a single command
+            if command != "takecards" {
+                break;
+            }
+        }
+        self.automatic[player_index].store(false, Ordering::SeqCst);
+        {
+            let mut game = self.game.lock().unwrap();
+            pick_up_received(&mut player, &mut game, player_index);
+            self.send_push(player_index, "timer", Payload::Seconds(0));
+            self.send_push(player_index, "hand", Payload::hand(&player.get_hand()));
+            self.update_spectators(&game);
+        }
+        drop(player);
+        self.save_snapshot();
+    }
+
+    pub fn inspect<F, R>(&self, player_index: usize, f: F) -> R
+    where
+        F: FnOnce(&Player, &TichuGame) -> R,
+    {
+        // look at the player and the game, e.g. for bots. the player's hand is complete
+        // because the cards from the exchange are picked up first
+        let mut player = self.players[player_index].lock().unwrap();
+        let mut game = self.game.lock().unwrap();
+        pick_up_received(&mut player, &mut game, player_index);
+        f(&player, &game)
+    }
+
+    pub fn handle_message(&self, player_index: usize, msg: &str) {
+        // the player's lock is held for the whole message, the game lock is acquired after it
+        let mut player = self.players[player_index].lock().unwrap();
+        self.handle_command(&mut player, player_index, msg);
+        // the command has released the game by now
+        self.update_spectators(&self.game.lock().unwrap());
+        drop(player);
+        self.save_snapshot();
+    }
+
+    fn save_snapshot(&self) {
+        // write the state of the whole game to disk, the players must not be locked. once the
+        // table is closed, the snapshot is removed
+        if self.is_closed() {
+            if let Err(e) = snapshot::remove(&self.snapshots, &self.table) {
+                error!("could not remove the snapshot: {}", e);
+            }
+            return;
+        }
+        // the players are locked in order, then the game like everywhere else
+        let [a, b, c, d] = &self.players;
+        let players = [
+            a.lock().unwrap(),
+            b.lock().unwrap(),
+            c.lock().unwrap(),
+            d.lock().unwrap(),
+        ];
+        let game = self.game.lock().unwrap();
+        let snapshot = Snapshot::new(
+            &self.table,
+            &self.seats,
+            [&players[0], &players[1], &players[2], &players[3]],
+            &game,
+            *self.rematch_votes.lock().unwrap(),
+            self.tournament.as_ref().map(|entry| entry.rotated),
+        );
+        if let Err(e) = snapshot.save(&self.snapshots) {
+            error!("could not save the snapshot: {}", e);
+        }
+    }
+
+    fn handle_command(&self, player: &mut Player, player_index: usize, msg: &str) {
+        // check for all the possible messages
+        if msg == "takecards" {
+            // acquire the lock to self.game
+            let mut game = self.game.lock().unwrap();
+            // the first eight cards start a new hand, the remaining six are added to it
+            match game.take_hand(player_index) {
+                Some(h) => {
+                    player.take_new_hand(h);
+                    self.answer_msg(player_index, Payload::cards_with_ids(&player.get_hand()));
+                }
+                None => match game.take_rest(player_index) {
+                    Some(h) => {
+                        // the new cards have the highest identifyers
+                        let count = h.len();
+                        player.add_cards(h);
+                        let hand = player.get_hand();
+                        let added = &hand[hand.len() - count..];
+                        self.answer_msg(player_index, Payload::cards_with_ids(added));
+                        // without an exchange, taking the cards is all there is to do
+                        if !game.rules().exchange {
+                            self.timer.lock().unwrap().stop(player_index);
+                            if game.exchange_complete() {
+                                self.finish_exchange(&game);
                             }
-                            player.take_new_hand(h);
-                        }
-                        _ => {
-                            debug!("a client tried to take a hand that does not exist");
-                            self.answer_err(
-                                player_index,
-                                "there is no hand for you at the moment",
-                            );
                         }
-                    };
-                // lock gets released at end of this scope
-                } else if msg.starts_with("play") && self.require_turn(player_index) {
-                    let args = parse_command_parameters(&msg);
-                    // check if it's the player's turn
-                    let mut game = self.game.lock().unwrap();
-                    let current_trick = game.get_current_trick();
-                    // let the player play against the current trick
-                    let played = player.play(current_trick, &args);
-                    match played {
-                        Ok(trick) => {
-                            self.answer_ok(player_index);
-                            self.send_push_to_all("newtrick", &format_hand(&trick.cards));
-                            debug!("the current trick is {:?}", &trick);
-                            game.add_trick(trick);
-                            self.continue_round(game, &player, player_index);
-                        }
-                        Err(PlayerError::NotValid) => self.answer_err(
-                            player_index,
-                            "Your cards don't form a valid trick",
-                        ),
-                        Err(PlayerError::TooLow) => self.answer_err(
-                            player_index,
-                            "Your trick is lower than the current trick",
-                        ),
-                        Err(PlayerError::Incompatible) => self.answer_err(
-                            player_index,
-                            "Your trick is incompatible with the current trick",
-                        ),
-                        Err(PlayerError::InvalidCard) => {
-                            error!("Player {} tried to play a card that is not in their hand anymore", player_index);
-                            self.answer_err(player_index, "something went wrong on the server side");
+                    }
+                    None => {
+                        debug!("a client tried to take a hand that does not exist");
+                        self.answer_err(player_index, "there is no hand for you at the moment");
+                    }
+                },
+            };
+        // lock gets released at end of this scope
+        } else if msg == "grandtichu" {
+            let mut game = self.game.lock().unwrap();
+            if !game.rules().grand_tichu {
+                self.answer_err(player_index, "Grand tichu is not played at this table");
+            } else if game.call_grand_tichu(player_index) {
+                self.answer_ok(player_index);
+                self.send_push_to_all("grandtichu", Payload::Seat(player_index));
+            } else {
+                self.answer_err(
+                    player_index,
+                    "You can only call grand tichu after seeing your first eight cards",
+                );
+            }
+        } else if msg == "tichu" {
+            let mut game = self.game.lock().unwrap();
+            if game.call_tichu(player_index) {
+                self.answer_ok(player_index);
+                self.send_push_to_all("tichu", Payload::Seat(player_index));
+            } else {
+                self.answer_err(
+                    player_index,
+                    "You can only call tichu once, after taking all your cards and before playing your first card",
+                );
+            }
+        } else if msg.starts_with("exchange") {
+            let args = match parse_command_parameters(msg) {
+                Ok(args) => args,
+                Err(e) => {
+                    self.answer_err(player_index, &e);
+                    return;
+                }
+            };
+            let mut game = self.game.lock().unwrap();
+            if !game.rules().exchange {
+                self.answer_err(player_index, "Cards are not exchanged at this table");
+            } else if game.exchange_complete() || game.has_exchanged(player_index) {
+                self.answer_err(player_index, "You can't exchange cards at the moment");
+            } else if !game.has_all_cards(player_index) {
+                self.answer_err(player_index, "Take all your cards before exchanging");
+            } else if args.len() != 3 {
+                self.answer_err(player_index, "You must pass exactly three cards");
+            } else {
+                match player.give_cards(&args) {
+                    Ok(cards) => {
+                        self.answer_ok(player_index);
+                        self.timer.lock().unwrap().stop(player_index);
+                        if game.exchange(player_index, cards) {
+                            self.finish_exchange(&game);
                         }
                     }
-                } else if msg == "pass" && self.require_turn(player_index) {
-                    let mut game = self.game.lock().unwrap();
-                    game.pass();
-                    self.answer_ok(player_index);
-                    self.continue_round(game, &player, player_index);
+                    Err(_) => self.answer_err(player_index, "You don't have these cards"),
+                }
+            }
+        } else if msg.starts_with("play") {
+            // whoever plays the one may wish for a kind, e.g. "play 0 3 Seven"
+            let (args, new_wish) = match parse_play_parameters(msg) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    self.answer_err(player_index, &e);
+                    return;
+                }
+            };
+            // the turn is checked and the trick is played while holding the same lock,
+            // so that nobody can play in between
+            let mut game = self.game.lock().unwrap();
+            if !self.require_play_allowed(&mut game, player, player_index) {
+                return;
+            }
+            if game.current_player != player_index {
+                // out of turn, only bombs are allowed (if the rules allow them at all)
+                if game.rules().bombs == BombTiming::AnyTime {
+                    self.play_bomb(game, player, player_index, &args);
                 } else {
-                    warn!("received invalid message from {}: {}", player.username, msg);
-                    // self.answer_err(player_index, "invalid command");
+                    self.answer_err(player_index, "It's not your turn");
+                }
+                return;
+            }
+            if new_wish.is_some() && !player.selects(&args, &Card::special(SpecialKind::One)) {
+                self.answer_err(
+                    player_index,
+                    "You can only make a wish when playing the One",
+                );
+                return;
+            }
+            let current_trick = game.get_current_trick();
+            // let the player play against the current trick
+            let played = player.play(current_trick, &args, game.get_wish());
+            match played {
+                Ok(trick) => {
+                    self.answer_ok(player_index);
+                    self.send_push_to_all("newtrick", Payload::trick(&trick));
+                    debug!("the current trick is {:?}", &trick);
+                    if game.fulfil_wish(&trick) {
+                        self.send_push_to_all("wish", Payload::None);
+                    }
+                    game.add_trick(trick);
+                    if let Some(kind) = new_wish {
+                        game.make_wish(kind);
+                        self.send_push_to_all("wish", Payload::text(&kind.to_string()));
+                    }
+                    self.continue_round(game, player, player_index);
+                }
+                Err(e) => self.answer_player_error(player_index, e),
+            }
+        } else if msg == "hints" {
+            // all tricks the player could play right now, only bombs if it's not their turn
+            let mut game = self.game.lock().unwrap();
+            if !game.exchange_complete() {
+                self.answer_err(
+                    player_index,
+                    "Wait until all players have exchanged their cards",
+                );
+                return;
+            }
+            pick_up_received(player, &mut game, player_index);
+            let trick = game.get_current_trick();
+            let mut moves = player.legal_moves(trick, game.get_wish());
+            if game.current_player != player_index {
+                let bombs = game.rules().bombs == BombTiming::AnyTime;
+                moves.retain(|(_, own)| bombs && own.is_bomb() && trick.is_some());
+            }
+            self.answer_msg(player_index, Payload::moves(&moves));
+        } else if msg == "pass" {
+            let mut game = self.game.lock().unwrap();
+            if !self.require_turn(&game, player_index)
+                || !self.require_play_allowed(&mut game, player, player_index)
+            {
+                return;
+            }
+            // whoever leads has to play something
+            if game.get_current_trick().is_none() {
+                self.answer_err(player_index, "You can't pass when leading");
+                return;
+            }
+            // passing is not allowed if the player could fulfil the wish
+            if let Some(kind) = game.get_wish() {
+                if player.can_fulfil_wish(game.get_current_trick(), kind) {
+                    self.answer_err(player_index, "You must play the wished card");
+                    return;
+                }
+            }
+            game.pass();
+            self.answer_ok(player_index);
+            self.continue_round(game, player, player_index);
+        } else if msg.starts_with("rematch") {
+            // "rematch yes" or "rematch no" once the game is over
+            let vote = match msg.split_whitespace().nth(1) {
+                Some("yes") => true,
+                Some("no") => false,
+                _ => {
+                    self.answer_err(player_index, "Vote with 'rematch yes' or 'rematch no'");
+                    return;
+                }
+            };
+            let game = self.game.lock().unwrap();
+            if !game.is_over() {
+                self.answer_err(player_index, "The game is not over yet");
+                return;
+            }
+            if self.is_closed() {
+                self.answer_err(player_index, "The table is closed");
+                return;
+            }
+            self.answer_ok(player_index);
+            self.vote_rematch(game, player_index, vote);
+        } else if msg.starts_with("givedragon") {
+            // "givedragon 1" gives the trick won with the dragon to player 1
+            let args = match parse_command_parameters(msg) {
+                Ok(args) => args,
+                Err(e) => {
+                    self.answer_err(player_index, &e);
+                    return;
+                }
+            };
+            let mut game = self.game.lock().unwrap();
+            if args.len() == 1 && game.give_dragon(player_index, args[0]) {
+                self.answer_ok(player_index);
+                self.send_push_to_all("dragon", Payload::Seat(args[0]));
+                self.send_push_to_all("cleartable", Payload::None);
+                self.send_turn(game.current_player);
+            } else {
+                self.answer_err(
+                    player_index,
+                    "You can only give the dragon to an opponent after winning with it",
+                );
+            }
+        } else {
+            warn!("received invalid message from {}: {}", player.username, msg);
+            // self.answer_err(player_index, "invalid command");
+        }
+    }
+
+    fn play_bomb(
+        &self,
+        mut game: MutexGuard<TichuGame>,
+        player: &mut Player,
+        player_index: usize,
+        args: &[usize],
+    ) {
+        // a bomb is checked against the trick that is on the table when the lock was acquired.
+        // if two players bomb at the same time, the first one to get the lock wins and the
+        // second bomb has to top the first one
+        let played = match game.get_current_trick() {
+            Some(trick) => player.play_bomb(trick, args),
+            None => Err(PlayerError::NotABomb),
+        };
+        match played {
+            Ok(trick) => {
+                self.answer_ok(player_index);
+                self.send_push_to_all("newtrick", Payload::trick(&trick));
+                debug!(
+                    "player {} interrupts with the bomb {:?}",
+                    player_index, &trick
+                );
+                if game.fulfil_wish(&trick) {
+                    self.send_push_to_all("wish", Payload::None);
                 }
-            } else if let Err(e) = line {
-                error!("Error while reading message for {}: {}", player.username, e);
+                // the bomber's left-hand neighbour continues
+                game.add_bomb(player_index, trick);
+                self.continue_round(game, player, player_index);
             }
+            Err(PlayerError::NotABomb) => self.answer_err(player_index, "It's not your turn"),
+            Err(e) => self.answer_player_error(player_index, e),
         }
     }
 
-    fn continue_round(&self, mut game: MutexGuard<TichuGame>, player: &Player, player_index: usize) {
+    fn continue_round(
+        &self,
+        mut game: MutexGuard<TichuGame>,
+        player: &Player,
+        player_index: usize,
+    ) {
         // this function rotates the current player and  checks for all
         // possible cases after a player finished their move
         let status = game.next();
         if status == RoundStatus::TrickWin {
-            self.send_push_to_all("cleartable", "");
+            self.send_push_to_all("cleartable", Payload::None);
+        }
+        if let Some(winner) = game.get_dragon_gift() {
+            // the winner must give the dragon away before anyone can go on playing
+            self.send_push(winner, "givedragon", Payload::None);
+            self.timer
+                .lock()
+                .unwrap()
+                .start_turn(winner, Instant::now());
         }
         if !player.has_cards() {
             match game.mark_finished(player_index) {
                 RoundStatus::FinishRound => {
                     game.shuffle_and_deal();
-                    self.send_push_to_all(&"cleartable", "");
-                    self.send_push_to_all(&"clearcards", "");
-                    debug!("this round is finished! new points: {:?}", game.get_current_score());
+                    self.timer.lock().unwrap().start_exchange(Instant::now());
+                    self.send_push_to_all("cleartable", Payload::None);
+                    self.send_push_to_all("clearcards", Payload::None);
+                    debug!(
+                        "this round is finished! new points: {:?}",
+                        game.get_current_score()
+                    );
+                }
+                RoundStatus::Team1Wins | RoundStatus::Team2Wins | RoundStatus::Draw => {
+                    self.timer.lock().unwrap().stop_all();
+                    self.send_push_to_all("cleartable", Payload::None);
+                    self.send_push_to_all("clearcards", Payload::None);
+                    self.send_push_to_all("gameover", Payload::scores(&game));
+                    info!(
+                        "the game is over! final score: {:?}, seeds of the rounds: {:?}",
+                        game.get_total_score(),
+                        game.get_round_seeds()
+                    );
+                    self.save_replay(&game);
+                    self.record_stats(&game);
+                    if let Some(entry) = &self.tournament {
+                        // the tournament goes on at the other tables, this one is done
+                        entry.tournament.record(
+                            &self.table,
+                            &self.usernames,
+                            entry.rotated,
+                            game.get_scores(),
+                        );
+                        self.send_push_to_all("shutdown", Payload::None);
+                        self.closed.store(true, Ordering::SeqCst);
+                    }
                 }
-                RoundStatus::Continue => {}
                 _ => {}
             };
         }
-        self.send_push(game.current_player, "yourturn", "");
+        // after a new deal, the game goes on once the cards are exchanged
+        if game.get_dragon_gift().is_none() && game.exchange_complete() && !game.is_over() {
+            self.send_turn(game.current_player);
+        }
+    }
+
+    fn vote_rematch(&self, mut game: MutexGuard<TichuGame>, player_index: usize, vote: bool) {
+        let mut votes = self.rematch_votes.lock().unwrap();
+        votes[player_index] = Some(vote);
+        self.send_push_to_all(
+            "rematch",
+            Payload::Vote(VoteData {
+                seat: player_index,
+                rematch: vote,
+            }),
+        );
+        if !vote {
+            // one vote against a rematch is enough to close the table, the players go back to the lobby
+            info!(
+                "player {} doesn't want a rematch, closing the table",
+                player_index
+            );
+            self.send_push_to_all("shutdown", Payload::None);
+            self.closed.store(true, Ordering::SeqCst);
+        } else if votes.iter().all(|v| *v == Some(true)) {
+            info!("everyone wants a rematch, starting a new game");
+            *votes = [None, None, None, None];
+            // the next seed, so that the rematch isn't dealt the same cards again
+            let seed = game.get_seed().wrapping_add(1);
+            *game = TichuGame::new(seed, game.rules().clone());
+            game.shuffle_and_deal();
+            self.timer.lock().unwrap().start_exchange(Instant::now());
+            self.send_push_to_all("newgame", Payload::None);
+        }
+    }
+
+    fn record_stats(&self, game: &TichuGame) {
+        // bots don't get statistics, but their rating counts for their team
+        let players = [0, 1, 2, 3].map(|i| {
+            let seat = &self.seats[i];
+            if seat.bot {
+                None
+            } else {
+                Some(seat.username.as_str())
+            }
+        });
+        self.stats.record(players, game);
+    }
+
+    fn save_replay(&self, game: &TichuGame) {
+        let replay = Replay::new(&self.table, &self.usernames, game.get_events());
+        match replay.save(&self.replays) {
+            Ok(path) => info!("saved the replay to {}", path.display()),
+            Err(e) => error!("could not save the replay: {}", e),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn finish_exchange(&self, game: &TichuGame) {
+        // tell everyone which cards they got, these cards get the identifyers following
+        // the fourteen dealt ones. without an exchange, the round just starts
+        for i in 0..4 {
+            if let Some(cards) = game.peek_received(i) {
+                let cards: Vec<(usize, &Card)> = (14..).zip(cards).collect();
+                self.send_push(i, "exchange", Payload::cards_with_ids(&cards));
+            }
+        }
+        self.send_turn(game.current_player);
+    }
+
+    fn require_play_allowed(
+        &self,
+        game: &mut MutexGuard<TichuGame>,
+        player: &mut Player,
+        player_index: usize,
+    ) -> bool {
+        // nobody can play before all cards are exchanged or while the dragon is being
+        // given away. once the cards are exchanged, the player picks up the ones they received
+        if !game.exchange_complete() {
+            self.answer_err(
+                player_index,
+                "Wait until all players have exchanged their cards",
+            );
+            return false;
+        }
+        if game.get_dragon_gift().is_some() {
+            self.answer_err(player_index, "Wait until the dragon has been given away");
+            return false;
+        }
+        if game.is_over() {
+            self.answer_err(player_index, "The game is over");
+            return false;
+        }
+        pick_up_received(player, game, player_index);
+        true
+    }
+
+    fn answer_player_error(&self, index: usize, error: PlayerError) {
+        match error {
+            PlayerError::NotValid => self.answer_err(index, "Your cards don't form a valid trick"),
+            PlayerError::TooLow => {
+                self.answer_err(index, "Your trick is lower than the current trick")
+            }
+            PlayerError::Incompatible => {
+                self.answer_err(index, "Your trick is incompatible with the current trick")
+            }
+            PlayerError::MustFulfilWish => self.answer_err(index, "You must play the wished card"),
+            PlayerError::NotABomb => self.answer_err(index, "Your cards don't form a bomb"),
+            PlayerError::InvalidCard => {
+                // stale or unknown identifyers, or the same card twice
+                info!("Player {} tried to play cards they don't have", index);
+                self.answer_err(index, "You don't have these cards");
+            }
+        }
     }
 
     fn answer_ok(&self, index: usize) {
-        self.send(index, "ok:");
+        self.send(index, Message::Ok(Payload::None));
     }
 
-    fn answer_msg(&self, index: usize, msg: &str) {
-        self.send(index, &format!("ok:{}", msg));
+    fn answer_msg(&self, index: usize, payload: Payload) {
+        self.send(index, Message::Ok(payload));
     }
 
     fn answer_err(&self, index: usize, msg: &str) {
-        self.send(index, &format!("err:{}", msg));
+        self.send(index, Message::Err(msg.to_string()));
     }
 
-    fn send(&self, index: usize, msg: &str) {
+    fn send(&self, index: usize, message: Message) {
+        // nobody waits for the answers to the commands of the timer
+        if self.automatic[index].load(Ordering::SeqCst) && !matches!(message, Message::Push(..)) {
+            return;
+        }
         // acquire lock for this stream
-        let mut stream = self.streams[index].lock().unwrap();
-        match stream.write(format!("{}\n", msg).as_bytes()) {
-            Ok(_) => {}
-            Err(e) => error!("could not send message '{}' to player {}: {}", msg, index, e),
-        };
+        self.streams[index].lock().unwrap().send(&message);
     }
 
-    pub fn send_to_stream(stream: &mut TcpStream, msg: &str) {
-        match stream.write(format!("{}\n", msg).as_bytes()) {
-            Ok(_) => {}
-            Err(e) => error!("could not send message '{}': {}", msg, e),
-        };
+    fn send_push_to_all(&self, topic: &'static str, payload: Payload) {
+        // send a push message to all clients in self.streams and to the spectators
+        for i in 0..4 {
+            self.send_push(i, topic, payload.clone());
+        }
+        self.send_push_to_spectators(topic, payload, false);
     }
 
-    fn send_push_to_all(&self, topic: &str, msg: &str) {
-        // send a push message to all clients in self.streams
-        for i in 0..4 {
-            self.send_push(i, topic, msg);
+    fn send_push_to_spectators(&self, topic: &'static str, payload: Payload, only_hands: bool) {
+        // only_hands: for the spectators who see the cards of all players
+        let message = Message::Push(topic, payload);
+        for spectator in self.spectators.lock().unwrap().iter_mut().flatten() {
+            if spectator.hands || !only_hands {
+                spectator.stream.send(&message);
+            }
+        }
+    }
+
+    fn send_turn(&self, index: usize) {
+        // spectators learn whose turn it is. the player's time starts now
+        self.timer.lock().unwrap().start_turn(index, Instant::now());
+        self.send_push(index, "yourturn", Payload::None);
+        self.send_push_to_spectators("turn", Payload::Seat(index), false);
+    }
+
+    fn hands(&self, game: &TichuGame) -> Payload {
+        // the cards that every player holds right now, following the log of the game
+        let mut viewer = Viewer::new(&self.usernames);
+        for event in game.get_events() {
+            viewer.apply(event);
         }
+        let hands: Vec<&[Card]> = (0..4).map(|i| viewer.hand(i)).collect();
+        Payload::hands(&hands)
     }
 
-    fn send_push(&self, index: usize, topic: &str, msg: &str) {
-        self.send(index, &format!("push:{}:{}", topic, msg));
+    fn update_spectators(&self, game: &TichuGame) {
+        // spectators who see the hands get them again whenever something has happened
+        let events = game.get_events().len();
+        if self.spectated.swap(events, Ordering::SeqCst) != events {
+            let watched = self
+                .spectators
+                .lock()
+                .unwrap()
+                .iter()
+                .flatten()
+                .any(|s| s.hands);
+            if watched {
+                self.send_push_to_spectators("hands", self.hands(game), true);
+            }
+        }
     }
 
-    fn require_turn(&self, player_index: usize) -> bool {
+    fn send_push(&self, index: usize, topic: &'static str, payload: Payload) {
+        self.send(index, Message::Push(topic, payload));
+    }
+
+    fn require_turn(&self, game: &TichuGame, player_index: usize) -> bool {
         // check if it's the player's turn
         // clients should themselves forbid to send commands if it's not their turn
-        // because checking on server side requires the lock on self.game
-        let game = self.game.lock().unwrap();
         if game.current_player == player_index {
             true
         } else {
@@ -193,68 +876,52 @@ impl TichuConnection {
 
 pub struct TichuServer {
     // Mutex<T> can be mutably accessed via a lock, Arc<T> allows multiple owners
-    inner: Arc<TichuConnection>,
+    lobby: Arc<Lobby>,
     listener: TcpListener,
-    join_handles: [Option<thread::JoinHandle<()>>; 4],
+    websocket_listener: Option<TcpListener>, // for browser clients
 }
 
 impl TichuServer {
-    pub fn accept(ip: &str, port: &str) -> Result<TichuServer, std::io::Error> {
-        let listener = match TcpListener::bind(format!("{}:{}", ip, port)) {
-            Ok(l) => {
-                info!("TichuConnection listening on {}:{}", ip, port);
-                l
+    pub fn bind(
+        ip: &str,
+        port: &str,
+        websocket_port: Option<&str>,
+        settings: Settings,
+    ) -> Result<TichuServer, std::io::Error> {
+        let listener = TcpListener::bind(format!("{}:{}", ip, port))?;
+        info!("TichuServer listening on {}:{}", ip, port);
+        let websocket_listener = match websocket_port {
+            Some(ws_port) => {
+                let l = TcpListener::bind(format!("{}:{}", ip, ws_port))?;
+                info!("TichuServer listening for websockets on {}:{}", ip, ws_port);
+                Some(l)
             }
-            Err(e) => return Err(e),
+            None => None,
         };
-        // accept first four incoming connections
-        let mut streams: [Option<TcpStream>; 4] = [None, None, None, None];
-        let mut i: usize = 0;
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let addr = stream.peer_addr().unwrap();
-                    info!("new connection with {}", addr);
-                    streams[i] = Some(stream);
-                    i += 1;
-                    if i == 4 {
-                        info!("connections complete, ready to start game");
-                        break;
-                    }
-                }
-                Err(e) => return Err(e),
-            }
-        }
-        let connections = [
-            Mutex::new(streams[0].take().unwrap()),
-            Mutex::new(streams[1].take().unwrap()),
-            Mutex::new(streams[2].take().unwrap()),
-            Mutex::new(streams[3].take().unwrap()),
-        ];
         Ok(TichuServer {
-            inner: Arc::new(TichuConnection::new(connections)),
-            listener: listener,
-            join_handles: [None, None, None, None],
+            lobby: Arc::new(Lobby::new(settings)),
+            listener,
+            websocket_listener,
         })
     }
 
+    pub fn resume(&self, restored: Restored) -> Result<(), String> {
+        // a table from a snapshot, before anyone can connect
+        self.lobby.resume(restored)
+    }
+
     pub fn main(&mut self) {
-        // shuffle and deal cards for everyone
-        {
-            let innerclone = self.inner.clone();
-            let mut game = innerclone.game.lock().unwrap();
-            game.shuffle_and_deal();
+        // websockets are accepted in their own thread, both kinds of clients end up in the same lobby
+        if let Some(listener) = &self.websocket_listener {
+            let listener = listener.try_clone().unwrap();
+            let lobby = self.lobby.clone();
+            thread::spawn(move || TichuServer::accept_all(listener, lobby, true));
         }
-        // spawn a thread for each player and listen to their incoming messages
-        for i in 0..4 {
-            let innerclone = self.inner.clone();
-            let handle = thread::spawn(move || {
-                innerclone.handle_connection(i)
-            });
-            self.join_handles[i] = Some(handle);
-        }
-        // let the threads do their job and wait til everything ends
-        self.join_all();
+        TichuServer::accept_all(
+            self.listener.try_clone().unwrap(),
+            self.lobby.clone(),
+            false,
+        );
     }
 
     pub fn stop(self) {
@@ -262,33 +929,142 @@ impl TichuServer {
         drop(self.listener);
     }
 
-    fn join_all(&mut self) {
-        for handle in &mut self.join_handles {
-            match handle.take().unwrap().join() {
-                Ok(_) => {}
-                Err(_) => error!("Could not join thread"),
+    fn accept_all(listener: TcpListener, lobby: Arc<Lobby>, websocket: bool) {
+        // spawn a thread for each connection and listen to their incoming messages
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let addr = stream.peer_addr().unwrap();
+                    info!("new connection with {}", addr);
+                    let lobby = lobby.clone();
+                    thread::spawn(move || {
+                        // the websocket handshake happens in the new thread, so that a slow
+                        // client can't block the others
+                        let connection = if websocket {
+                            Client::websocket(stream)
+                        } else {
+                            Some(Client::tcp(stream))
+                        };
+                        if let Some((client, incoming)) = connection {
+                            TichuServer::serve(lobby, client, incoming);
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("could not accept connection: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn serve(lobby: Arc<Lobby>, mut client: Client, mut incoming: Incoming) {
+        // say hello
+        client.send(&Message::Ok(Payload::None));
+        // get the username and the protocol first
+        let hello = match incoming.next() {
+            Some(hello) => hello,
+            None => return,
+        };
+        let login = match protocol::handshake(&hello) {
+            Ok((protocol, login)) => {
+                client.protocol = protocol;
+                login
             }
+            Err(e) => {
+                // whoever sends a broken hello is probably trying to speak json
+                client.protocol = Protocol::Json;
+                client.send(&Message::Err(e));
+                return;
+            }
+        };
+        // line clients that just send their username don't get an answer
+        let answer = client.protocol == Protocol::Json || !matches!(login, Login::Guest(_));
+        let username = match lobby.authenticate(login) {
+            Ok(username) => username,
+            Err(e) => {
+                client.send(&Message::Err(e));
+                return;
+            }
+        };
+        if answer {
+            client.send(&Message::Ok(Payload::None));
         }
-        info!("all treads joined");
+        let mut visitor = Visitor::new(username, client);
+
+        // main loop waiting for commands
+        for line in incoming {
+            debug!("got message from {}: {}", visitor.username, line);
+            let msg = match protocol::parse_request(visitor.protocol(), &line) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    visitor.answer_err(&e);
+                    continue;
+                }
+            };
+            // chat works at the table before and during the game. as soon as the game at the
+            // visitor's table has started, all other messages go there
+            match chat::parse(&msg) {
+                Some(Ok(chat)) => lobby.chat(&mut visitor, &chat),
+                Some(Err(e)) => visitor.answer_err(&e),
+                None => match lobby.game_of(&mut visitor) {
+                    Some((game, seat)) => game.handle_message(seat, &msg),
+                    None => lobby.handle_message(&mut visitor, &msg),
+                },
+            }
+        }
+        // a seat at a running game is kept, the player can come back with their token
+        info!("{} disconnected", visitor.username);
+        lobby.leave(&mut visitor);
     }
 }
 
-fn format_hand(hand: &Vec<Card>) -> String {
-    let mut str = String::new();
-    for card in hand {
-        str += &format!("{},", card.to_string());
+fn public_state(game: &TichuGame) -> Vec<(&'static str, Payload)> {
+    // what everyone at the table knows: rules, calls, current trick, wish, scores and turn
+    let mut pushes = vec![("rules", Payload::Rules(game.rules().clone()))];
+    for i in 0..4 {
+        match game.get_call(i) {
+            Some(Call::Tichu) => pushes.push(("tichu", Payload::Seat(i))),
+            Some(Call::GrandTichu) => pushes.push(("grandtichu", Payload::Seat(i))),
+            None => {}
+        }
+    }
+    if let Some(trick) = game.get_current_trick() {
+        pushes.push(("newtrick", Payload::trick(trick)));
     }
-    str.to_string()
+    if let Some(kind) = game.get_wish() {
+        pushes.push(("wish", Payload::text(&kind.to_string())));
+    }
+    pushes.push(("scores", Payload::scores(game)));
+    pushes.push(("turn", Payload::Seat(game.current_player)));
+    pushes
 }
 
-fn parse_command_parameters(command: &str) -> Vec<usize> {
-    // parse something like "command 1 2" into (1, 2)
-    let mut parts = command.split_whitespace();
-    // ignore first part
-    parts.next();
-    let mut args: Vec<usize> = Vec::new();
-    for p in parts {
-        args.push(p.parse().unwrap());
+fn pick_up_received(player: &mut Player, game: &mut TichuGame, player_index: usize) {
+    // the cards from the exchange are added to the hand the first time the player needs them
+    if let Some(cards) = game.take_received(player_index) {
+        player.add_cards(cards);
     }
-    return args
+}
+
+fn parse_command_parameters(command: &str) -> Result<Vec<usize>, String> {
+    // parse something like "command 1 2" into (1, 2), the first part is ignored
+    command
+        .split_whitespace()
+        .skip(1)
+        .map(|p| p.parse().map_err(|_| format!("'{}' is not a number", p)))
+        .collect()
+}
+
+fn parse_play_parameters(command: &str) -> Result<(Vec<usize>, Option<RegularKind>), String> {
+    // parse something like "play 0 3 Seven" into (0, 3) and the wish, which can only come last
+    let mut parts: Vec<&str> = command.split_whitespace().collect();
+    let wish = match parts.last().and_then(|p| p.parse().ok()) {
+        Some(kind) if parts.len() > 1 => {
+            parts.pop();
+            Some(kind)
+        }
+        _ => None,
+    };
+    Ok((parse_command_parameters(&parts.join(" "))?, wish))
 }
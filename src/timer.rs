@@ -0,0 +1,297 @@
+use crate::bot;
+use crate::player::Player;
+use crate::tichugame::TichuGame;
+use crate::tichuserver::TichuConnection;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how often the timer thread of a table looks at the deadlines
+const TICK: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    // how long a player may take, no limit if None
+    pub turn: Option<Duration>, // for a move or for giving away the dragon
+    pub exchange: Option<Duration>, // for taking the cards and exchanging after a deal
+    pub warning: Duration,      // the player is warned when only this much time is left
+}
+
+impl Limits {
+    pub fn enabled(&self) -> bool {
+        self.turn.is_some() || self.exchange.is_some()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Alarm {
+    Warning(usize, u64), // seat and seconds left
+    Expired(usize),
+}
+
+#[derive(Clone, Copy)]
+struct Deadline {
+    at: Instant,
+    warned: bool,
+}
+
+pub struct Timer {
+    limits: Limits,
+    deadlines: [Option<Deadline>; 4], // by seat
+}
+
+impl Timer {
+    pub fn new(limits: Limits) -> Timer {
+        Timer {
+            limits,
+            deadlines: [None; 4],
+        }
+    }
+
+    pub fn start_turn(&mut self, seat: usize, now: Instant) {
+        // only one player is on the clock during the play
+        self.stop_all();
+        self.deadlines[seat] = self.limits.turn.map(|limit| Deadline {
+            at: now + limit,
+            warned: false,
+        });
+    }
+
+    pub fn start_exchange(&mut self, now: Instant) {
+        // after a deal, everyone has to take their cards and exchange
+        let deadline = self.limits.exchange.map(|limit| Deadline {
+            at: now + limit,
+            warned: false,
+        });
+        self.deadlines = [deadline; 4];
+    }
+
+    pub fn stop(&mut self, seat: usize) {
+        self.deadlines[seat] = None;
+    }
+
+    pub fn stop_all(&mut self) {
+        self.deadlines = [None; 4];
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Vec<Alarm> {
+        // warnings for the players who are running out of time, expired deadlines are removed
+        let mut alarms = Vec::new();
+        for (seat, slot) in self.deadlines.iter_mut().enumerate() {
+            if let Some(deadline) = slot {
+                let left = deadline.at.saturating_duration_since(now);
+                if left == Duration::from_secs(0) {
+                    alarms.push(Alarm::Expired(seat));
+                    *slot = None;
+                } else if !deadline.warned && left <= self.limits.warning {
+                    // round up, so that nobody is told they have 0 seconds left
+                    let seconds = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+                    alarms.push(Alarm::Warning(seat, seconds));
+                    deadline.warned = true;
+                }
+            }
+        }
+        alarms
+    }
+}
+
+pub fn spawn(game: Arc<TichuConnection>) {
+    // looks at the deadlines of a table until it is closed
+    thread::spawn(move || {
+        while !game.is_closed() {
+            thread::sleep(TICK);
+            game.check_timer(Instant::now());
+        }
+    });
+}
+
+pub fn default_command(player: &Player, game: &TichuGame, seat: usize) -> Option<String> {
+    // what the server does for a player who ran out of time: take the cards and exchange the
+    // lowest ones, give the dragon to the next opponent, pass if possible and otherwise play
+    // the lowest trick. the dragon and the phoenix are kept like the bots do. the cards the
+    // player received must have been picked up
+    if game.is_over() {
+        return None;
+    }
+    if !game.exchange_complete() {
+        if !game.has_all_cards(seat) {
+            return Some("takecards".to_string());
+        }
+        if game.has_exchanged(seat) || !game.rules().exchange {
+            return None;
+        }
+        let mut hand = player.get_hand();
+        // the phoenix has rank 0 but is no low card
+        hand.sort_by_key(|(_, c)| (bot::is_high_special(c), c.rank));
+        // the two lowest cards go to the opponents
+        return Some(format!(
+            "exchange {} {} {}",
+            hand[0].0, hand[2].0, hand[1].0
+        ));
+    }
+    match game.get_dragon_gift() {
+        Some(winner) if winner == seat => return Some(format!("givedragon {}", (seat + 1) % 4)),
+        Some(_) => return None,
+        None => {}
+    }
+    if game.current_player != seat {
+        return None;
+    }
+    let trick = game.get_current_trick();
+    let forced = game
+        .get_wish()
+        .is_some_and(|kind| player.can_fulfil_wish(trick, kind));
+    if trick.is_some() && !forced {
+        return Some("pass".to_string());
+    }
+    let lowest = bot::lowest_move(player, trick, game.get_wish())?;
+    let ids: Vec<String> = lowest.iter().map(|i| i.to_string()).collect();
+    Some(format!("play {}", ids.join(" ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleSet;
+
+    fn limits() -> Limits {
+        Limits {
+            turn: Some(Duration::from_secs(30)),
+            exchange: None,
+            warning: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut timer = Timer::new(limits());
+        let start = Instant::now();
+        timer.start_turn(1, start);
+        assert_eq!(timer.poll(start + Duration::from_secs(5)), vec![]);
+        assert_eq!(
+            timer.poll(start + Duration::from_millis(20500)),
+            vec![Alarm::Warning(1, 10)]
+        );
+        // the warning comes only once
+        assert_eq!(timer.poll(start + Duration::from_secs(25)), vec![]);
+        // a new turn starts the clock for another player
+        timer.start_turn(2, start + Duration::from_secs(25));
+        assert_eq!(timer.poll(start + Duration::from_secs(31)), vec![]);
+        assert_eq!(
+            timer.poll(start + Duration::from_secs(55)),
+            vec![Alarm::Expired(2)]
+        );
+        assert_eq!(timer.poll(start + Duration::from_secs(60)), vec![]);
+        // there's no limit for the exchange
+        timer.start_exchange(start);
+        assert_eq!(timer.poll(start + Duration::from_secs(3600)), vec![]);
+    }
+
+    #[test]
+    fn test_default_command() {
+        let mut game = TichuGame::new(1, RuleSet::default());
+        game.shuffle_and_deal();
+        let mut players: Vec<Player> = (0..4).map(|i| Player::new(i.to_string())).collect();
+        assert_eq!(
+            default_command(&players[0], &game, 0),
+            Some("takecards".to_string())
+        );
+        for (i, player) in players.iter_mut().enumerate() {
+            player.take_new_hand(game.take_hand(i).unwrap());
+            player.add_cards(game.take_rest(i).unwrap());
+            // the three lowest cards are exchanged, the dragon and the phoenix are kept
+            let command = default_command(player, &game, i).unwrap();
+            let ids: Vec<usize> = command
+                .split_whitespace()
+                .skip(1)
+                .map(|id| id.parse().unwrap())
+                .collect();
+            let hand = player.get_hand();
+            let card = |id: &usize| hand.iter().find(|(i, _)| i == id).unwrap().1;
+            assert!(!ids.iter().map(card).any(bot::is_high_special));
+            let mut ranks: Vec<i16> = ids.iter().map(|id| card(id).rank).collect();
+            let mut lowest: Vec<i16> = hand
+                .iter()
+                .filter(|(_, c)| !bot::is_high_special(c))
+                .map(|(_, c)| c.rank)
+                .collect();
+            ranks.sort();
+            lowest.sort();
+            assert_eq!(ranks, lowest[..3]);
+            let cards = player.give_cards(&ids).unwrap();
+            game.exchange(i, cards);
+            assert_eq!(default_command(player, &game, i), None);
+        }
+        for (i, player) in players.iter_mut().enumerate() {
+            player.add_cards(game.take_received(i).unwrap());
+            // the received cards follow the fourteen dealt ones, as the exchange push says
+            let ids: Vec<usize> = player.get_hand().iter().map(|(i, _)| *i).collect();
+            assert_eq!(ids[11..], [14, 15, 16]);
+        }
+        // whoever has the one leads the lowest trick, the others have to wait
+        let first = game.current_player;
+        let command = default_command(&players[first], &game, first).unwrap();
+        assert!(command.starts_with("play "));
+        assert_eq!(
+            default_command(&players[(first + 1) % 4], &game, (first + 1) % 4),
+            None
+        );
+        let ids: Vec<usize> = command[5..]
+            .split(' ')
+            .map(|id| id.parse().unwrap())
+            .collect();
+        let trick = players[first].play(None, &ids, None).unwrap();
+        assert!(!trick.cards.iter().any(bot::is_high_special));
+        game.add_trick(trick);
+        game.next();
+        let next = game.current_player;
+        assert_eq!(
+            default_command(&players[next], &game, next),
+            Some("pass".to_string())
+        );
+    }
+
+    #[test]
+    fn test_default_command_leading() {
+        // the time of the player who won the last trick runs out, leading can't be skipped
+        let rules = RuleSet {
+            exchange: false,
+            ..RuleSet::default()
+        };
+        let mut game = TichuGame::new(2, rules);
+        game.shuffle_and_deal();
+        let mut players: Vec<Player> = (0..4).map(|i| Player::new(i.to_string())).collect();
+        for (i, player) in players.iter_mut().enumerate() {
+            player.take_new_hand(game.take_hand(i).unwrap());
+            player.add_cards(game.take_rest(i).unwrap());
+        }
+        let first = game.current_player;
+        let command = default_command(&players[first], &game, first).unwrap();
+        let ids: Vec<usize> = command[5..]
+            .split(' ')
+            .map(|id| id.parse().unwrap())
+            .collect();
+        game.add_trick(players[first].play(None, &ids, None).unwrap());
+        game.next();
+        // everyone else passes, the trick goes to the first player who has to lead again
+        while game.get_current_trick().is_some() {
+            let next = game.current_player;
+            assert_eq!(
+                default_command(&players[next], &game, next),
+                Some("pass".to_string())
+            );
+            game.pass();
+            game.next();
+        }
+        assert_eq!(game.current_player, first);
+        let mut timer = Timer::new(limits());
+        let start = Instant::now();
+        timer.start_turn(first, start);
+        assert_eq!(
+            timer.poll(start + Duration::from_secs(30)),
+            vec![Alarm::Expired(first)]
+        );
+        let command = default_command(&players[first], &game, first).unwrap();
+        assert!(command.starts_with("play "));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::deck::{Card, Deck};
+use crate::tichugame::Deal;
+use itertools::Itertools;
+use log::{error, info};
+use serde::Deserialize;
+use std::convert::TryInto;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Deserialize)]
+struct DealEntry {
+    // a line of the deal file, either a seed or the cards of each player
+    seed: Option<u64>,
+    hands: Option<Vec<Vec<Card>>>,
+}
+
+struct TableResult {
+    table: String,
+    players: [String; 4],
+    rotated: bool,
+    points: Vec<[i16; 2]>, // points of the teams 0,2 and 1,3 for every deal
+}
+
+pub struct Tournament {
+    // in a duplicate tournament every table plays the same deals and the teams are compared
+    // to those that held the same cards at other tables
+    deals: Vec<Deal>,
+    report: PathBuf,                  // where the comparison is written to
+    tables: Mutex<usize>,             // number of tables that have started so far
+    results: Mutex<Vec<TableResult>>, // of the tables that have played all deals
+}
+
+pub struct Entry {
+    // a table's part in the tournament
+    pub tournament: Arc<Tournament>,
+    // at every second table, each player gets the cards of their right neighbour, so that the
+    // teams swap the cards compared to the other tables
+    pub rotated: bool,
+}
+
+impl Tournament {
+    pub fn load(path: &Path, report: PathBuf) -> Result<Tournament, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let deals = read_deals(BufReader::new(file))?;
+        info!("loaded {} deals for the tournament", deals.len());
+        Ok(Tournament::new(deals, report))
+    }
+
+    fn new(deals: Vec<Deal>, report: PathBuf) -> Tournament {
+        Tournament {
+            deals,
+            report,
+            tables: Mutex::new(0),
+            results: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn enter(tournament: &Arc<Tournament>) -> Entry {
+        // assign the deals to a table that is about to start
+        let mut tables = tournament.tables.lock().unwrap();
+        let rotated = *tables % 2 == 1;
+        *tables += 1;
+        Entry {
+            tournament: tournament.clone(),
+            rotated,
+        }
+    }
+
+    pub fn record(&self, table: &str, players: &[String; 4], rotated: bool, scores: &[Vec<i16>]) {
+        // the scores of a table that has played all deals, the report is updated right away
+        let mut results = self.results.lock().unwrap();
+        results.push(TableResult {
+            table: table.to_string(),
+            players: players.clone(),
+            rotated,
+            points: scores.iter().map(|p| [p[0], p[1]]).collect(),
+        });
+        let report = report(&self.deals, &results);
+        info!("tournament results so far:\n{}", report);
+        let written = match self.report.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&self.report, report)),
+            None => fs::write(&self.report, report),
+        };
+        match written {
+            Ok(_) => info!("saved the tournament report to {}", self.report.display()),
+            Err(e) => error!("could not save the tournament report: {}", e),
+        }
+    }
+}
+
+impl Entry {
+    pub fn deals(&self) -> Vec<Deal> {
+        self.tournament
+            .deals
+            .iter()
+            .map(|deal| {
+                let mut deal = deal.clone();
+                if self.rotated {
+                    deal.hands.rotate_right(1);
+                }
+                deal
+            })
+            .collect()
+    }
+}
+
+fn read_deals<R: BufRead>(input: R) -> Result<Vec<Deal>, String> {
+    // one deal per line, e.g. {"seed": 42} or {"hands": [["Red Five", ...], ...]}
+    let mut deals = Vec::new();
+    for (n, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |e: String| format!("deal on line {}: {}", n + 1, e);
+        let entry: DealEntry = serde_json::from_str(&line).map_err(|e| error(e.to_string()))?;
+        let deal = match entry {
+            DealEntry {
+                seed: Some(seed),
+                hands: None,
+            } => Deal::shuffled(seed),
+            DealEntry {
+                seed: None,
+                hands: Some(hands),
+            } => Deal {
+                seed: None,
+                hands: check_hands(hands).map_err(error)?,
+            },
+            _ => return Err(error("give either a seed or the hands".to_string())),
+        };
+        deals.push(deal);
+    }
+    if deals.is_empty() {
+        return Err("there are no deals in the file".to_string());
+    }
+    Ok(deals)
+}
+
+fn check_hands(hands: Vec<Vec<Card>>) -> Result<[Vec<Card>; 4], String> {
+    // four hands of 14 cards that make up the whole deck
+    if hands.len() != 4 || hands.iter().any(|hand| hand.len() != 14) {
+        return Err("there must be four hands of 14 cards".to_string());
+    }
+    let names = |cards: &mut dyn Iterator<Item = &Card>| -> Vec<String> {
+        cards.map(|c| c.to_string()).sorted().collect()
+    };
+    if names(&mut hands.iter().flatten()) != names(&mut Deck::new().cards.iter()) {
+        return Err("every card must be dealt exactly once".to_string());
+    }
+    Ok(hands.try_into().unwrap())
+}
+
+fn report(deals: &[Deal], results: &[TableResult]) -> String {
+    // for every deal, each team is compared to the teams that held the same cards at the other
+    // tables: it scores the difference between its points and their average. the teams are
+    // ranked by the sum of these differences
+    let mut out = String::new();
+    let team = |result: &TableResult, team: usize| {
+        format!("{} & {}", result.players[team], result.players[team + 2])
+    };
+    // the teams that held the cards of the players 0 and 2 (and 1 and 3) in the deal file
+    let sides = |result: &TableResult| if result.rotated { [1, 0] } else { [0, 1] };
+    let mut totals = vec![[0.0, 0.0]; results.len()];
+    let _ = writeln!(
+        out,
+        "duplicate tournament: {} deals, {} tables",
+        deals.len(),
+        results.len()
+    );
+    for (d, deal) in deals.iter().enumerate() {
+        let _ = match deal.seed {
+            Some(seed) => writeln!(out, "\ndeal {} (seed {})", d + 1, seed),
+            None => writeln!(out, "\ndeal {}", d + 1),
+        };
+        // the points of each side, the first entry of the scores is the empty score
+        let points: Vec<[i16; 2]> = results
+            .iter()
+            .map(|r| {
+                let p = r.points.get(d + 1).copied().unwrap_or([0, 0]);
+                let [a, b] = sides(r);
+                [p[a], p[b]]
+            })
+            .collect();
+        let average =
+            |side: usize| points.iter().map(|p| p[side] as f32).sum::<f32>() / points.len() as f32;
+        let averages = [average(0), average(1)];
+        for (t, result) in results.iter().enumerate() {
+            let teams: Vec<String> = (0..2)
+                .map(|side| {
+                    let difference = points[t][side] as f32 - averages[side];
+                    totals[t][sides(result)[side]] += difference;
+                    format!(
+                        "{} {} ({:+.1})",
+                        team(result, sides(result)[side]),
+                        points[t][side],
+                        difference
+                    )
+                })
+                .collect();
+            let _ = writeln!(out, "  {}: {} : {}", result.table, teams[0], teams[1]);
+        }
+    }
+    let mut ranking: Vec<(String, f32)> = results
+        .iter()
+        .zip(totals)
+        .flat_map(|(r, total)| {
+            (0..2).map(move |i| (format!("{} ({})", team(r, i), r.table), total[i]))
+        })
+        .collect();
+    ranking.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    let _ = writeln!(out, "\nranking");
+    for (i, (team, total)) in ranking.iter().enumerate() {
+        let _ = writeln!(out, "  {}. {} {:+.1}", i + 1, team, total);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players(names: &str) -> [String; 4] {
+        let names: Vec<String> = names.split(',').map(|s| s.to_string()).collect();
+        names.try_into().unwrap()
+    }
+
+    #[test]
+    fn test_read_deals() {
+        let hands = Deal::shuffled(5).hands;
+        let given = serde_json::json!({ "hands": hands }).to_string();
+        let input = format!("{{\"seed\": 5}}\n\n{}\n", given);
+        let deals = read_deals(input.as_bytes()).unwrap();
+        assert_eq!(deals.len(), 2);
+        assert_eq!(deals[0], Deal::shuffled(5));
+        assert_eq!(deals[1].seed, None);
+        assert_eq!(deals[1].hands, hands);
+        // a card is missing and another one is there twice
+        let mut broken = hands.clone();
+        broken[0][0] = broken[1][0];
+        let broken = serde_json::json!({ "hands": broken }).to_string();
+        assert!(read_deals(broken.as_bytes()).is_err());
+        assert!(read_deals(&b"{\"seed\": 1, \"hands\": []}"[..]).is_err());
+        assert!(read_deals(&b""[..]).is_err());
+    }
+
+    #[test]
+    fn test_rotated_deals() {
+        let tournament = Arc::new(Tournament::new(vec![Deal::shuffled(1)], PathBuf::new()));
+        let first = Tournament::enter(&tournament);
+        let second = Tournament::enter(&tournament);
+        assert!(!first.rotated && second.rotated);
+        let deal = &tournament.deals[0];
+        assert_eq!(first.deals()[0].hands, deal.hands);
+        // player 1 gets the cards of player 0
+        assert_eq!(second.deals()[0].hands[1], deal.hands[0]);
+    }
+
+    #[test]
+    fn test_report() {
+        let deals = vec![Deal::shuffled(1)];
+        let results = vec![
+            TableResult {
+                table: "t1".to_string(),
+                players: players("a,b,c,d"),
+                rotated: false,
+                points: vec![[0, 0], [100, 0]],
+            },
+            TableResult {
+                table: "t2".to_string(),
+                players: players("e,f,g,h"),
+                rotated: true,
+                // f and h held the cards of a and c
+                points: vec![[0, 0], [50, 50]],
+            },
+        ];
+        let report = report(&deals, &results);
+        assert!(report.contains("t1: a & c 100 (+25.0) : b & d 0 (-25.0)"));
+        assert!(report.contains("t2: f & h 50 (-25.0) : e & g 50 (+25.0)"));
+        assert!(report.contains("ranking\n  1. a & c (t1) +25.0\n  2. e & g (t2) +25.0\n"));
+        assert!(report.contains("4. f & h (t2) -25.0"));
+    }
+}
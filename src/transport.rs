@@ -0,0 +1,226 @@
+use crate::protocol::{Message, Protocol};
+use bufstream::BufStream;
+use log::{debug, error};
+use std::io::{self, BufRead, Lines, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tungstenite::protocol::Role;
+use tungstenite::{Message as WsMessage, WebSocket};
+
+#[derive(Clone)]
+enum Transport {
+    // clones of a client share the same writer, so that messages sent from different
+    // threads (lobby, game, spectators) don't get mixed up
+    Bot(Sender<Message>),       // messages are handed to a bot thread
+    Tcp(Arc<Mutex<TcpStream>>), // one message per line
+    WebSocket(Arc<Mutex<WebSocket<WsStream>>>), // one message per text frame
+    Delayed(Sender<(Instant, Message)>, Duration), // messages reach a client after a while
+    Disconnected, // a restored seat whose player hasn't come back yet, messages are dropped
+}
+
+#[derive(Clone)]
+pub struct Client {
+    // a connection to a client together with the protocol it speaks
+    transport: Transport,
+    pub protocol: Protocol,
+    peer: Option<IpAddr>, // address of the other end, None for bots and disconnected seats
+}
+
+impl Client {
+    pub fn tcp(stream: TcpStream) -> (Client, Incoming) {
+        // clone the stream because BufStream::new() and lines() take ownership
+        // use the lines to iterate over incoming messages, this has the advantage
+        // that it blocks the thread until there is a new line as opposed to
+        // loop { stream.read() }.
+        let incoming = Incoming::Tcp(BufStream::new(stream.try_clone().unwrap()).lines());
+        let client = Client {
+            peer: stream.peer_addr().ok().map(|addr| addr.ip()),
+            transport: Transport::Tcp(Arc::new(Mutex::new(stream))),
+            protocol: Protocol::Line,
+        };
+        (client, incoming)
+    }
+
+    pub fn websocket(stream: TcpStream) -> Option<(Client, Incoming)> {
+        // the websocket that did the handshake reads, a second one on the same stream writes.
+        // both write their frames through the same lock, the reader answers pings
+        let writer = Arc::new(Mutex::new(stream.try_clone().unwrap()));
+        let reader = WsStream {
+            stream: stream.try_clone().unwrap(),
+            writer: writer.clone(),
+        };
+        let socket = match tungstenite::accept(reader) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("websocket handshake failed: {}", e);
+                return None;
+            }
+        };
+        let peer = stream.peer_addr().ok().map(|addr| addr.ip());
+        let writer = WsStream { stream, writer };
+        let client = Client {
+            transport: Transport::WebSocket(Arc::new(Mutex::new(WebSocket::from_raw_socket(
+                writer,
+                Role::Server,
+                None,
+            )))),
+            protocol: Protocol::Line,
+            peer,
+        };
+        Some((client, Incoming::WebSocket(Box::new(socket))))
+    }
+
+    pub fn bot(sender: Sender<Message>) -> Client {
+        Client {
+            transport: Transport::Bot(sender),
+            protocol: Protocol::Line,
+            peer: None,
+        }
+    }
+
+    pub fn disconnected() -> Client {
+        Client {
+            transport: Transport::Disconnected,
+            protocol: Protocol::Line,
+            peer: None,
+        }
+    }
+
+    pub fn delayed(mut client: Client, delay: Duration) -> Client {
+        // a thread holds the messages back until they are due
+        let (sender, inbox) = channel::<(Instant, Message)>();
+        let protocol = client.protocol;
+        let peer = client.peer;
+        thread::spawn(move || {
+            for (due, message) in inbox.iter() {
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+                client.send(&message);
+            }
+        });
+        Client {
+            transport: Transport::Delayed(sender, delay),
+            protocol,
+            peer,
+        }
+    }
+
+    pub fn is_bot(&self) -> bool {
+        matches!(self.transport, Transport::Bot(_))
+    }
+
+    pub fn peer(&self) -> Option<IpAddr> {
+        self.peer
+    }
+
+    pub fn send(&mut self, message: &Message) {
+        match &self.transport {
+            Transport::Bot(sender) => {
+                // the bot is gone once its game is over, nobody needs to know
+                let _ = sender.send(message.clone());
+                return;
+            }
+            Transport::Delayed(sender, delay) => {
+                let _ = sender.send((Instant::now() + *delay, message.clone()));
+                return;
+            }
+            Transport::Disconnected => return,
+            _ => {}
+        }
+        let msg = match self.protocol {
+            Protocol::Line => message.to_line(),
+            Protocol::Json => message.to_json(),
+        };
+        let result = match &self.transport {
+            Transport::Bot(_) | Transport::Delayed(..) | Transport::Disconnected => Ok(()),
+            Transport::Tcp(stream) => stream
+                .lock()
+                .unwrap()
+                .write_all(format!("{}\n", msg).as_bytes())
+                .map_err(|e| e.to_string()),
+            Transport::WebSocket(socket) => socket
+                .lock()
+                .unwrap()
+                .send(WsMessage::Text(msg.clone()))
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            error!("could not send message '{}': {}", msg, e);
+        }
+    }
+
+    pub fn shutdown(&self) {
+        // closing the stream ends the loop that reads from it
+        let _ = match &self.transport {
+            Transport::Bot(_) | Transport::Delayed(..) | Transport::Disconnected => return,
+            Transport::Tcp(stream) => stream.lock().unwrap().shutdown(Shutdown::Both),
+            Transport::WebSocket(socket) => socket
+                .lock()
+                .unwrap()
+                .get_ref()
+                .stream
+                .shutdown(Shutdown::Both),
+        };
+    }
+}
+
+pub struct WsStream {
+    // the stream of a websocket, which reads by itself but writes through the lock that
+    // all websockets of the connection share, so that their frames don't interleave
+    stream: TcpStream,
+    writer: Arc<Mutex<TcpStream>>,
+}
+
+impl Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // a websocket writes whole frames at once, they are written completely while locked
+        self.writer.lock().unwrap().write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+pub enum Incoming {
+    // the messages a client sends, ends when the connection is closed
+    Tcp(Lines<BufStream<TcpStream>>),
+    WebSocket(Box<WebSocket<WsStream>>),
+}
+
+impl Iterator for Incoming {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        match self {
+            Incoming::Tcp(lines) => match lines.next()? {
+                Ok(line) => Some(line),
+                Err(e) => {
+                    error!("Error while reading message: {}", e);
+                    None
+                }
+            },
+            Incoming::WebSocket(socket) => loop {
+                // pings are answered by tungstenite itself, only text frames are of interest
+                match socket.read() {
+                    Ok(WsMessage::Text(text)) => return Some(text.trim_end().to_string()),
+                    Ok(WsMessage::Close(_)) => return None,
+                    Ok(other) => debug!("ignoring websocket message {:?}", other),
+                    Err(e) => {
+                        error!("Error while reading message: {}", e);
+                        return None;
+                    }
+                }
+            },
+        }
+    }
+}